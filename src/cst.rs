@@ -0,0 +1,241 @@
+use std::fmt;
+use std::ops::Range;
+use regex::Regex;
+use lazy_static::lazy_static;
+use crate::diagnostic::Span;
+
+lazy_static! {
+    pub static ref CONFIG_REGEX: Regex = Regex::new(r#"^\s*(-?)((?:"[^"]*"|[a-zA-Z0-9._/*?\[\]-])+)\s*=\s*(.*?)\s*$"#).unwrap();
+    pub static ref COMMENT_REGEX: Regex = Regex::new(r"^\s*[#;]").unwrap();
+}
+
+// 設定ファイルを、コメント・空行・空白・改行コード・行の順序・キーの元の表記を含めてそのまま保持する構文木。
+// 編集せずに書き出すと元のファイルとバイト単位で一致する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub lines: Vec<Line>,
+}
+
+// `raw` は改行コードや継続行の `\` を含む元の文字列そのもの、
+// `text` は末尾が `\` の行を次の行と連結した論理行（改行コードを含まない）
+// `number` は論理行の先頭の物理行の、1始まりの行番号
+// `pieces` は論理行を構成する物理行ごとの位置で、`text` 上の位置を元のファイル上の位置に戻すのに使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub number: usize,
+    pub raw: String,
+    pub text: String,
+    pub kind: LineKind,
+    pieces: Vec<Piece>,
+}
+
+// 論理行のうち1つの物理行から来た部分。`offset` は `text` 上の開始位置、
+// `column` は物理行の行頭からの開始位置（連結時に取り除いた先頭の空白の分だけずれる）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Piece {
+    number: usize,
+    offset: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    Blank,
+    Comment,
+    Entry(EntryNode),
+    Invalid,
+}
+
+// `key`・`value` は `text` の先頭からのバイトオフセットで表した範囲。
+// `value` は前後の空白を含まず、`key = ` のように値がない場合は空の範囲になる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryNode {
+    pub ignore_failure: bool,
+    pub key: Range<usize>,
+    pub value: Range<usize>,
+}
+
+impl Document {
+    // 連結する行の先頭の空白は論理行からは取り除く。コメント行は連結しない
+    pub fn parse(source: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut pending: Option<(String, String, Vec<Piece>)> = None;
+
+        for (index, physical) in source.split_inclusive('\n').enumerate() {
+            let content = physical.strip_suffix('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                .unwrap_or(physical);
+            let (mut raw, mut text, pieces) = match pending.take() {
+                Some((raw, mut text, mut pieces)) => {
+                    let trimmed = content.trim_start();
+                    pieces.push(Piece { number: index + 1, offset: text.len(), column: content.len() - trimmed.len() });
+                    text.push_str(trimmed);
+                    (raw, text, pieces)
+                }
+                None if COMMENT_REGEX.is_match(content) => {
+                    lines.push(Line::new(index + 1, physical.to_string(), content.to_string()));
+                    continue;
+                }
+                None => (String::new(), content.to_string(), vec![Piece { number: index + 1, offset: 0, column: 0 }]),
+            };
+            raw.push_str(physical);
+
+            if text.ends_with('\\') {
+                text.pop();
+                pending = Some((raw, text, pieces));
+            } else {
+                lines.push(Line::with_pieces(raw, text, pieces));
+            }
+        }
+        if let Some((raw, text, pieces)) = pending {
+            lines.push(Line::with_pieces(raw, text, pieces));
+        }
+        Document { lines }
+    }
+
+    // `index` 番目の行の `text` の `range` を `value` に置き換える。継続行で書かれた行は1行にまとめる
+    pub fn replace_text(&mut self, index: usize, range: Range<usize>, value: &str) {
+        let line = &self.lines[index];
+        let mut text = line.text.clone();
+        text.replace_range(range, value);
+        let raw = format!("{}{}", text, line_ending(&line.raw));
+        self.lines[index] = Line::new(line.number, raw, text);
+    }
+
+    pub fn remove_line(&mut self, index: usize) {
+        self.lines.remove(index);
+    }
+
+    // 末尾に行を追加する。改行コードはファイルの最初の行に合わせる。
+    // 最後の行に改行がない場合は改行を補い、`\` で終わっている場合は空行を挟んで継続行として連結されないようにする
+    pub fn push_line(&mut self, text: &str) {
+        let ending = self.lines.first()
+            .map(|line| line_ending(&line.raw))
+            .filter(|ending| !ending.is_empty())
+            .unwrap_or("\n");
+        if let Some(last) = self.lines.last_mut() {
+            if !last.raw.ends_with('\n') {
+                last.raw.push_str(ending);
+            }
+            if last.kind != LineKind::Comment && last.raw.trim_end_matches(['\r', '\n']).ends_with('\\') {
+                last.raw.push_str(ending);
+            }
+        }
+        let number = self.lines.iter().map(|line| line.raw.matches('\n').count()).sum::<usize>() + 1;
+        self.lines.push(Line::new(number, format!("{}{}", text, ending), text.to_string()));
+    }
+}
+
+fn line_ending(raw: &str) -> &'static str {
+    if raw.ends_with("\r\n") {
+        "\r\n"
+    } else if raw.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.lines.iter().try_for_each(|line| f.write_str(&line.raw))
+    }
+}
+
+impl Line {
+    fn new(number: usize, raw: String, text: String) -> Self {
+        Line::with_pieces(raw, text, vec![Piece { number, offset: 0, column: 0 }])
+    }
+
+    // `text` 上のバイトオフセットの範囲を、元のファイルの物理行上の位置に変換する。
+    // 範囲が複数の物理行にまたがる場合は、開始位置の物理行の末尾までにする
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let index = self.pieces.iter().rposition(|piece| piece.offset <= start).unwrap_or(0);
+        let piece = self.pieces[index];
+        let piece_end = self.pieces.get(index + 1).map_or(self.text.len(), |next| next.offset);
+        let end = end.clamp(start, piece_end.max(start));
+        Span::new(piece.number, piece.column + start - piece.offset, piece.column + end - piece.offset)
+    }
+
+    fn with_pieces(raw: String, text: String, pieces: Vec<Piece>) -> Self {
+        let kind = if text.trim().is_empty() {
+            LineKind::Blank
+        } else if COMMENT_REGEX.is_match(&text) {
+            LineKind::Comment
+        } else if let Some(captures) = CONFIG_REGEX.captures(&text) {
+            LineKind::Entry(EntryNode {
+                ignore_failure: !captures[1].is_empty(),
+                key: captures.get(2).unwrap().range(),
+                value: captures.get(3).unwrap().range(),
+            })
+        } else {
+            LineKind::Invalid
+        };
+        Line { number: pieces[0].number, raw, text, kind, pieces }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(source: &str) {
+        assert_eq!(Document::parse(source).to_string(), source);
+    }
+
+    #[test]
+    fn round_trip_crlf() {
+        assert_round_trip("a = 1\r\n\r\nb = 2\r\n");
+    }
+
+    #[test]
+    fn round_trip_missing_final_newline() {
+        assert_round_trip("a = 1\nb = 2");
+    }
+
+    #[test]
+    fn round_trip_trailing_backslash() {
+        let source = "a = x \\\n   y\nb = 2\n";
+        assert_round_trip(source);
+        let document = Document::parse(source);
+        assert_eq!(document.lines.len(), 2);
+        assert_eq!(document.lines[0].text, "a = x y");
+    }
+
+    #[test]
+    fn round_trip_file_ending_mid_continuation() {
+        assert_round_trip("a = 1 \\\n");
+        assert_round_trip("a = 1 \\");
+        assert_round_trip("a = 1 \\\r\n  2 \\\r\n");
+    }
+
+    #[test]
+    fn round_trip_comments() {
+        let source = "# hash comment \\\n; semicolon comment\n  # indented\nkey = value # inline\n";
+        assert_round_trip(source);
+        let kinds: Vec<_> = Document::parse(source).lines.into_iter().map(|line| line.kind).collect();
+        assert!(matches!(kinds[..], [LineKind::Comment, LineKind::Comment, LineKind::Comment, LineKind::Entry(_)]));
+    }
+
+    #[test]
+    fn round_trip_empty_file() {
+        assert_round_trip("");
+        assert!(Document::parse("").lines.is_empty());
+    }
+
+    #[test]
+    fn round_trip_invalid_and_blank_lines() {
+        assert_round_trip("  \t\nnot an entry\n\n-key.with/slash = \"quoted # value\"  \n");
+    }
+
+    #[test]
+    fn span_maps_logical_offsets_to_physical_lines() {
+        let document = Document::parse("# c\na = \"x \\\n   y\\q\"\n");
+        let line = &document.lines[1];
+        assert_eq!(line.text, "a = \"x y\\q\"");
+        assert_eq!(line.span(0, 1), Span::new(2, 0, 1));
+        assert_eq!(line.span(8, 10), Span::new(3, 4, 6));
+        // 物理行をまたぐ範囲は開始位置の物理行の末尾までにする
+        assert_eq!(line.span(4, 10), Span::new(2, 4, 7));
+    }
+}
@@ -0,0 +1,197 @@
+use std::path::Path;
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Ja,
+    En,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn label(self, lang: Lang) -> &'static str {
+        match (self, lang) {
+            (Severity::Error, Lang::Ja) => "エラー",
+            (Severity::Error, Lang::En) => "error",
+            (Severity::Warning, Lang::Ja) => "警告",
+            (Severity::Warning, Lang::En) => "warning",
+        }
+    }
+}
+
+// `line` は1始まりの行番号、`start`・`end` は行頭からのバイトオフセット
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, start: usize, end: usize) -> Self {
+        Span { line, start, end }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub span: Span,
+    pub label: String,
+}
+
+// `code` は診断の種類を表す機械向けの識別子（`missing-equals` など）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub label: Option<String>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: String, span: Span) -> Self {
+        Diagnostic { severity, code, message, span, label: None, notes: Vec::new() }
+    }
+
+    pub fn with_label(mut self, label: String) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn with_note(mut self, span: Span, label: String) -> Self {
+        self.notes.push(Note { span, label });
+        self
+    }
+
+    pub fn to_json(&self, source: &str) -> serde_json::Value {
+        let text = source.lines().nth(self.span.line.wrapping_sub(1)).unwrap_or("");
+        json!({
+            "severity": self.severity.as_str(),
+            "code": self.code,
+            "line": self.span.line,
+            "column": column(text, self.span.start),
+            "end_column": column(text, self.span.end.max(self.span.start)),
+            "message": self.message,
+        })
+    }
+
+    // コンパイラ風に「ファイル:行:列」、該当行、問題箇所の下のキャレットを出力する
+    pub fn render(&self, path: &Path, source: &str, lang: Lang) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let line_text = |line: usize| lines.get(line.wrapping_sub(1)).copied().unwrap_or("");
+        let width = self.notes.iter()
+            .map(|note| note.span.line)
+            .chain(std::iter::once(self.span.line))
+            .max()
+            .unwrap_or(0)
+            .to_string()
+            .len();
+        let gutter = " ".repeat(width);
+
+        let text = line_text(self.span.line);
+        let mut out = format!("{}[{}]: {}\n", self.severity.label(lang), self.code, self.message);
+        out.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter, path.display(), self.span.line, column(text, self.span.start)
+        ));
+        out.push_str(&format!("{} |\n", gutter));
+        push_snippet(&mut out, width, text, self.span, '^', self.label.as_deref());
+        for note in &self.notes {
+            push_snippet(&mut out, width, line_text(note.span.line), note.span, '-', Some(&note.label));
+        }
+        out
+    }
+}
+
+// バイトオフセットを1始まりの文字単位の列番号に変換する
+fn column(text: &str, offset: usize) -> usize {
+    text[..clamp_offset(text, offset)].chars().count() + 1
+}
+
+fn clamp_offset(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+fn push_snippet(out: &mut String, width: usize, text: &str, span: Span, marker: char, label: Option<&str>) {
+    let start = clamp_offset(text, span.start);
+    let end = clamp_offset(text, span.end.max(span.start));
+    // タブはそのまま残して、キャレットの位置がずれないようにする
+    let padding: String = text[..start].chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let markers = marker.to_string().repeat(text[start..end].chars().count().max(1));
+
+    out.push_str(&format!("{:>width$} | {}\n", span.line, text, width = width));
+    out.push_str(&format!("{:width$} | {}{}", "", padding, markers, width = width));
+    if let Some(label) = label {
+        out.push_str(&format!(" {}", label));
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "a = 1\n\tキー = x\n";
+
+    fn diagnostic() -> Diagnostic {
+        Diagnostic::new(Severity::Error, "test-code", "message".to_string(), Span::new(2, 1, 7))
+            .with_label("label".to_string())
+            .with_note(Span::new(1, 0, 1), "note".to_string())
+    }
+
+    #[test]
+    fn renders_japanese_with_tab_padding_and_multibyte_carets() {
+        assert_eq!(
+            diagnostic().render(Path::new("f.conf"), SOURCE, Lang::Ja),
+            "エラー[test-code]: message\n --> f.conf:2:2\n  |\n2 | \tキー = x\n  | \t^^ label\n1 | a = 1\n  | - note\n",
+        );
+    }
+
+    #[test]
+    fn renders_english_warning_without_label() {
+        let warning = Diagnostic::new(Severity::Warning, "test-code", "message".to_string(), Span::new(1, 4, 5));
+        assert_eq!(
+            warning.render(Path::new("f.conf"), SOURCE, Lang::En),
+            "warning[test-code]: message\n --> f.conf:1:5\n  |\n1 | a = 1\n  |     ^\n",
+        );
+    }
+
+    #[test]
+    fn gutter_widens_for_two_digit_line_numbers() {
+        let source = "x\n".repeat(9) + "bad\n";
+        let error = Diagnostic::new(Severity::Error, "test-code", "message".to_string(), Span::new(10, 0, 3));
+        assert_eq!(
+            error.render(Path::new("f.conf"), &source, Lang::En),
+            "error[test-code]: message\n  --> f.conf:10:1\n   |\n10 | bad\n   | ^^^\n",
+        );
+    }
+
+    #[test]
+    fn json_columns_count_characters() {
+        let json = diagnostic().to_json(SOURCE);
+        assert_eq!(json["line"], 2);
+        assert_eq!(json["column"], 2);
+        assert_eq!(json["end_column"], 4);
+        assert_eq!(json["code"], "test-code");
+    }
+}
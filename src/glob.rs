@@ -0,0 +1,131 @@
+use regex::Regex;
+
+// シェル形式のグロブパターン（`*`、`?`、`[...]`）を正規表現に変換して照合する
+#[derive(Debug, Clone)]
+pub struct Glob {
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let regex = Regex::new(&to_regex(pattern))?;
+        Ok(Glob { regex })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+pub fn is_glob(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+fn to_regex(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::from("^");
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => match class_end(&chars, i) {
+                Some(end) => {
+                    regex.push('[');
+                    let mut j = i + 1;
+                    if matches!(chars[j], '!' | '^') {
+                        regex.push('^');
+                        j += 1;
+                    }
+                    for &c in &chars[j..end] {
+                        // `-` 以外の記号は文字クラス内でもエスケープしておく
+                        if c != '-' && !c.is_alphanumeric() {
+                            regex.push('\\');
+                        }
+                        regex.push(c);
+                    }
+                    regex.push(']');
+                    i = end;
+                }
+                None => regex.push_str(r"\["),
+            },
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        i += 1;
+    }
+
+    regex.push('$');
+    regex
+}
+
+// `[` に対応する `]` の位置を返す。`[]...]` や `[!]...]` の先頭の `]` は文字として扱う
+fn class_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if matches!(chars.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        i += 1;
+    }
+    (i..chars.len()).find(|&j| chars[j] == ']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Glob::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn star_matches_any_sequence() {
+        assert!(matches("*.conf", "a.conf"));
+        assert!(matches("*.conf", ".conf"));
+        assert!(!matches("*.conf", "a.conf.bak"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("eth?", "eth0"));
+        assert!(!matches("eth?", "eth"));
+        assert!(!matches("eth?", "eth10"));
+    }
+
+    #[test]
+    fn negated_class() {
+        assert!(matches("[!x]", "a"));
+        assert!(!matches("[!x]", "x"));
+        assert!(matches("[^x]", "a"));
+    }
+
+    #[test]
+    fn leading_bracket_in_class_is_literal() {
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[]a]", "a"));
+        assert!(!matches("[]a]", "b"));
+    }
+
+    #[test]
+    fn unclosed_bracket_is_literal() {
+        assert!(matches("x[1", "x[1"));
+        assert!(!matches("x[1", "x1"));
+    }
+
+    #[test]
+    fn regex_metacharacters_are_literal() {
+        assert!(matches("a.b+", "a.b+"));
+        assert!(!matches("a.b+", "axbb"));
+        assert!(matches("[.+]", "+"));
+        assert!(!matches("[.+]", "a"));
+    }
+
+    #[test]
+    fn detects_glob_characters() {
+        assert!(is_glob("net.*.rp_filter"));
+        assert!(is_glob("eth?"));
+        assert!(is_glob("[ab]"));
+        assert!(!is_glob("net.ipv4.ip_forward"));
+    }
+}
@@ -1,115 +1,1901 @@
-use std::collections::HashMap;
+mod cst;
+mod diagnostic;
+mod glob;
+mod sysctld;
+
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use indexmap::IndexMap;
 use regex::Regex;
 use lazy_static::lazy_static;
 use serde_json::json;
+use cst::{Document, LineKind};
+use diagnostic::{Diagnostic, Lang, Severity, Span};
+use glob::{is_glob, Glob};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+// 終了コード: 全ファイル成功、一部失敗、全て失敗、`get`・`unset` のキーが未定義、引数の誤り
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_PARTIAL_FAILURE: i32 = 1;
+const EXIT_FAILURE: i32 = 2;
+const EXIT_KEY_NOT_FOUND: i32 = 3;
+const EXIT_USAGE: i32 = 64;
+
+// Empty は `key =` のように `=` の後に何も書かれていない値（`""` と書いた値は String）
+// UInt は i64 に収まらない u64 の範囲の整数（`kernel.shmmax = 18446744073692774399` など）
+#[derive(Debug, Clone, PartialEq)]
 enum ConfigValue {
+    Empty,
     String(String),
-    Map(HashMap<String, ConfigValue>),
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Array(Vec<ConfigValue>),
+    Map(IndexMap<String, ConfigValue>),
 }
 
+// ディレクトリ指定時に読み飛ばすエディタのバックアップやパッケージマネージャの残骸
+const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
+    "*~", "*.swp", "*.swo", ".#*", "#*#", "*.bak", "*.orig",
+    "*.rpmsave", "*.rpmnew", "*.rpmorig",
+    "*.dpkg-old", "*.dpkg-new", "*.dpkg-dist", "*.dpkg-bak", "*.dpkg-tmp",
+    "*.ucf-old", "*.ucf-new", "*.ucf-dist",
+    "README*",
+];
+
 lazy_static! {
-    static ref CONFIG_REGEX: Regex = Regex::new(r"^\s*([a-zA-Z0-9._-]+)\s*=\s*(.+?)\s*$").unwrap();
-    static ref COMMENT_REGEX: Regex = Regex::new(r"^\s*#").unwrap();
+    static ref KEY_CHAR_REGEX: Regex = Regex::new(r#"^[a-zA-Z0-9._/*?\[\]"-]$"#).unwrap();
+    static ref DEFAULT_IGNORE_GLOBS: Vec<Glob> = DEFAULT_IGNORE_PATTERNS.iter()
+        .map(|pattern| Glob::new(pattern).unwrap())
+        .collect();
+    static ref FLOAT_REGEX: Regex = Regex::new(r"^[+-]?((\d+\.\d*|\.\d+)([eE][+-]?\d+)?|\d+[eE][+-]?\d+)$").unwrap();
+}
+
+// Strict: 文法エラーが1行でもあればファイル全体をスキップする
+// Lenient: 文法エラーの行だけを読み飛ばし、読み飛ばした行を報告する
+// Partial: 途中で止まらず、キーの衝突があっても解釈できた部分をすべての診断とともに返す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseMode {
+    Strict,
+    Lenient,
+    Partial,
+}
+
+// スカラー値とマップが同じキーで衝突したときの扱い
+// Error: ファイルをエラーとして扱う
+// LastWins: 後に現れた定義で上書きする
+// KeepScalar: スカラー値を予約キー `_value` の子として残す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConflictPolicy {
+    Error,
+    LastWins,
+    KeepScalar,
+}
+
+const SCALAR_VALUE_KEY: &str = "_value";
+
+// 値の途中の `#`・`;` の扱い
+// None: コメントとして扱わず値に含める（sysctl と同じ）
+// Strip: 空白の後の `#`・`;` 以降をコメントとして取り除く
+// Warn: 値に含めたまま、コメントのように見える箇所を警告する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InlineCommentPolicy {
+    None,
+    Strip,
+    Warn,
+}
+
+// `infer_types` が false の場合はすべての値を文字列として扱う
+// `split_values` が true の場合は空白を含むすべての値を、
+// `split_keys` に含まれるキーの値は常に、空白区切りの配列として扱う
+// `sysctl_root` が指定されている場合は、グロブを含むキーをその下に実在するキーに展開する
+// `normalize_keys` が true の場合は、キーの空のセグメント（`a..b`、`.a`、`a.`）を警告して取り除く
+#[derive(Debug, Clone)]
+struct ParseOptions {
+    mode: ParseMode,
+    conflict_policy: ConflictPolicy,
+    infer_types: bool,
+    split_values: bool,
+    split_keys: Vec<Vec<String>>,
+    sysctl_root: Option<PathBuf>,
+    inline_comments: InlineCommentPolicy,
+    normalize_keys: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            mode: ParseMode::Strict,
+            conflict_policy: ConflictPolicy::Error,
+            infer_types: true,
+            split_values: false,
+            split_keys: Vec::new(),
+            sysctl_root: None,
+            inline_comments: InlineCommentPolicy::None,
+            normalize_keys: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SyntaxErrorKind {
+    MissingEquals,
+    EmptyKey,
+    EmptyKeySegment,
+    EmptyKeySegmentRemoved,
+    InvalidKeyChar(char),
+    Malformed,
+    UnterminatedQuote,
+    InvalidEscape(char),
+    TrailingCharacters,
+    SuspiciousComment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SyntaxError {
+    kind: SyntaxErrorKind,
+    span: Span,
 }
 
-fn parse_config_file(file_path: &Path) -> io::Result<HashMap<String, ConfigValue>> {
-    let file = fs::File::open(file_path)?;
-    let reader = BufReader::new(file);
-    let mut config = HashMap::new();
+impl SyntaxError {
+    // CONFIG_REGEX に一致しなかった行から、エラーの種類と位置を特定する
+    fn classify(line: usize, text: &str) -> Self {
+        let start = text.len() - text.trim_start().len();
+        let end = text.trim_end().len();
+        let Some(eq) = text.find('=') else {
+            return SyntaxError { kind: SyntaxErrorKind::MissingEquals, span: Span::new(line, start, end) };
+        };
 
-    for line in reader.lines().flatten() {
-        let trimmed_line = line.trim();
-        if COMMENT_REGEX.is_match(trimmed_line) || trimmed_line.is_empty() {
-            continue; // コメント行・空行をスキップ
+        let eq_span = Span::new(line, eq, eq + 1);
+        if text[..eq].trim().is_empty() {
+            return SyntaxError { kind: SyntaxErrorKind::EmptyKey, span: eq_span };
         }
+        let key_end = text[..eq].trim_end().len();
+        if let Some(quote) = text[start..key_end].find('"')
+            && text[start..key_end].matches('"').count() % 2 == 1
+        {
+            let quote = start + text[start..key_end].rfind('"').unwrap_or(quote);
+            return SyntaxError { kind: SyntaxErrorKind::UnterminatedQuote, span: Span::new(line, quote, quote + 1) };
+        }
+        let invalid = text[start..key_end].char_indices()
+            .find(|(_, c)| !KEY_CHAR_REGEX.is_match(c.encode_utf8(&mut [0; 4])));
+        match invalid {
+            Some((offset, c)) => SyntaxError {
+                kind: SyntaxErrorKind::InvalidKeyChar(c),
+                span: Span::new(line, start + offset, start + offset + c.len_utf8()),
+            },
+            None => SyntaxError { kind: SyntaxErrorKind::Malformed, span: Span::new(line, start, end) },
+        }
+    }
 
-        if let Some(captures) = CONFIG_REGEX.captures(trimmed_line) {
-            let key = captures[1].to_string();
-            let raw_value = captures[2].trim().to_string();
-            insert_config_value(&mut config, &key, ConfigValue::String(raw_value));
+    fn code(&self) -> &'static str {
+        match self.kind {
+            SyntaxErrorKind::MissingEquals => "missing-equals",
+            SyntaxErrorKind::EmptyKey => "empty-key",
+            SyntaxErrorKind::EmptyKeySegment | SyntaxErrorKind::EmptyKeySegmentRemoved => "empty-key-segment",
+            SyntaxErrorKind::InvalidKeyChar(_) => "invalid-key-char",
+            SyntaxErrorKind::Malformed => "malformed-line",
+            SyntaxErrorKind::UnterminatedQuote => "unterminated-quote",
+            SyntaxErrorKind::InvalidEscape(_) => "invalid-escape",
+            SyntaxErrorKind::TrailingCharacters => "trailing-characters",
+            SyntaxErrorKind::SuspiciousComment => "suspicious-comment",
         }
     }
 
-    Ok(config)
+    fn to_diagnostic(&self, severity: Severity, lang: Lang) -> Diagnostic {
+        let message = match (&self.kind, lang) {
+            (SyntaxErrorKind::MissingEquals, Lang::Ja) => "`=` がありません".to_string(),
+            (SyntaxErrorKind::MissingEquals, Lang::En) => "expected `=` after key".to_string(),
+            (SyntaxErrorKind::EmptyKey, Lang::Ja) => "`=` の前にキーがありません".to_string(),
+            (SyntaxErrorKind::EmptyKey, Lang::En) => "missing key before `=`".to_string(),
+            (SyntaxErrorKind::EmptyKeySegment, Lang::Ja) => "キーに空のセグメントがあります（`--normalize-keys` で取り除けます）".to_string(),
+            (SyntaxErrorKind::EmptyKeySegment, Lang::En) => "empty segment in key (use `--normalize-keys` to remove it)".to_string(),
+            (SyntaxErrorKind::EmptyKeySegmentRemoved, Lang::Ja) => "キーの空のセグメントを取り除きました".to_string(),
+            (SyntaxErrorKind::EmptyKeySegmentRemoved, Lang::En) => "removed empty segment from key".to_string(),
+            (SyntaxErrorKind::InvalidKeyChar(c), Lang::Ja) => format!("キーに使用できない文字 `{}` が含まれています", c),
+            (SyntaxErrorKind::InvalidKeyChar(c), Lang::En) => format!("invalid character `{}` in key", c),
+            (SyntaxErrorKind::Malformed, Lang::Ja) => "行を解釈できません".to_string(),
+            (SyntaxErrorKind::Malformed, Lang::En) => "malformed line".to_string(),
+            (SyntaxErrorKind::UnterminatedQuote, Lang::Ja) => "引用符が閉じられていません".to_string(),
+            (SyntaxErrorKind::UnterminatedQuote, Lang::En) => "unterminated quoted value".to_string(),
+            (SyntaxErrorKind::InvalidEscape(c), Lang::Ja) => format!("不明なエスケープシーケンス `\\{}` です", c),
+            (SyntaxErrorKind::InvalidEscape(c), Lang::En) => format!("unknown escape sequence `\\{}`", c),
+            (SyntaxErrorKind::TrailingCharacters, Lang::Ja) => "引用符で囲まれた値の後に余分な文字があります".to_string(),
+            (SyntaxErrorKind::TrailingCharacters, Lang::En) => "unexpected characters after quoted value".to_string(),
+            (SyntaxErrorKind::SuspiciousComment, Lang::Ja) => "値の途中にコメントのような文字列があります（値の一部として扱います）".to_string(),
+            (SyntaxErrorKind::SuspiciousComment, Lang::En) => "value contains what looks like a trailing comment (kept as part of the value)".to_string(),
+        };
+        Diagnostic::new(severity, self.code(), message, self.span)
+    }
+}
+
+// `span` は衝突を起こした定義のキー、`defined` は先に定義されたキーの位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyConflict {
+    key: String,
+    span: Span,
+    defined: Span,
 }
 
-fn insert_config_value(config: &mut HashMap<String, ConfigValue>, key: &str, value: ConfigValue) {
-    let keys: Vec<&str> = key.split('.').collect();
+impl KeyConflict {
+    fn to_diagnostic(&self, lang: Lang) -> Diagnostic {
+        let (message, label, note) = match lang {
+            Lang::Ja => (
+                format!("キー `{}` が値とマップの両方として定義されています", self.key),
+                "ここで衝突しています".to_string(),
+                "ここで先に定義されています".to_string(),
+            ),
+            Lang::En => (
+                format!("key `{}` is defined both as a value and as a map", self.key),
+                "conflicting definition".to_string(),
+                "first defined here".to_string(),
+            ),
+        };
+        Diagnostic::new(Severity::Error, "key-conflict", message, self.span)
+            .with_label(label)
+            .with_note(self.defined, note)
+    }
+}
+
+// `dropped` は読み飛ばした文法エラーの行、`conflicts` は衝突のため採用しなかった定義
+#[derive(Debug)]
+struct ParsedConfig {
+    config: IndexMap<String, ConfigValue>,
+    entries: Vec<Entry>,
+    dropped: Vec<SyntaxError>,
+    warnings: Vec<SyntaxError>,
+    conflicts: Vec<KeyConflict>,
+}
+
+impl ParsedConfig {
+    // 読み飛ばした行と採用しなかった定義をエラー、それ以外を警告として、行順に並べた診断を返す
+    fn diagnostics(&self, lang: Lang) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = self.dropped.iter()
+            .map(|e| e.to_diagnostic(Severity::Error, lang))
+            .chain(self.warnings.iter().map(|e| e.to_diagnostic(Severity::Warning, lang)))
+            .chain(self.conflicts.iter().map(|c| c.to_diagnostic(lang)))
+            .collect();
+        diagnostics.sort_by_key(|d| (d.span.line, d.span.start));
+        diagnostics
+    }
+}
+
+// ファイル内の1つの定義。値と、それが書かれていた位置を保持する
+// `ignore_failure` はキーの先頭に `-` が付いていた（適用に失敗しても無視する）ことを表す
+// `pattern` はグロブを含むキーを展開して得た定義の場合に、展開元のキーを保持する
+// `comment` は値の後ろから取り除いた行内コメント（`#`・`;` を含む）を保持する
+// `key` は `path` を表示用にドット区切りで書き直したもの
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    key: String,
+    path: KeyPath,
+    value: ConfigValue,
+    span: Span,
+    ignore_failure: bool,
+    pattern: Option<String>,
+    comment: Option<String>,
+}
+
+#[derive(Debug)]
+enum ParseError {
+    Syntax(Vec<SyntaxError>),
+    Conflict(Vec<KeyConflict>),
+}
+
+fn parse_config_str(source: &str, options: &ParseOptions) -> Result<ParsedConfig, ParseError> {
+    let parsed = parse_config_partial(source, options);
+    if options.mode == ParseMode::Strict && !parsed.dropped.is_empty() {
+        return Err(ParseError::Syntax(parsed.dropped));
+    }
+    if !parsed.conflicts.is_empty() {
+        return Err(ParseError::Conflict(parsed.conflicts));
+    }
+    Ok(parsed)
+}
+
+// エディタやリンター向けに、エラーがあっても途中で止まらずに最後まで解釈する。
+// 文法エラーの行と衝突した定義は読み飛ばし、それ以外から組み立てた設定を返す
+fn parse_config_partial(source: &str, options: &ParseOptions) -> ParsedConfig {
+    let mut config = IndexMap::new();
+    let mut entries = Vec::new();
+    let mut defined_at = HashMap::new();
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut glob_entries = Vec::new();
+
+    for line in Document::parse(source).lines {
+        let node = match &line.kind {
+            LineKind::Blank | LineKind::Comment => continue, // コメント行・空行をスキップ
+            LineKind::Invalid => {
+                let error = SyntaxError::classify(line.number, &line.text);
+                errors.push(SyntaxError { span: line.span(error.span.start, error.span.end), ..error });
+                continue;
+            }
+            LineKind::Entry(node) => node.clone(),
+        };
+
+        let key = node.key;
+        let key_span = |start: usize, end: usize| line.span(key.start + start, key.start + end);
+        let (path, empty_segments) = match parse_key_path(&line.text[key.clone()]) {
+            Ok(parsed) => parsed,
+            Err((kind, start, end)) => {
+                errors.push(SyntaxError { kind, span: key_span(start, end) });
+                continue;
+            }
+        };
+        if !empty_segments.is_empty() {
+            let spans = empty_segments.iter().map(|&i| key_span(i, i + 1));
+            if options.normalize_keys && !path.segments.is_empty() {
+                warnings.extend(spans.map(|span| SyntaxError { kind: SyntaxErrorKind::EmptyKeySegmentRemoved, span }));
+            } else {
+                errors.extend(spans.map(|span| SyntaxError { kind: SyntaxErrorKind::EmptyKeySegment, span }));
+                continue;
+            }
+        }
+        let normalized_key = format_key(&path.segments);
+        let value_range = node.value;
+        let mut raw_value = &line.text[value_range.clone()];
+        let mut comment = None;
+        if let Some(comment_start) = find_inline_comment(raw_value) {
+            let comment_span = line.span(value_range.start + comment_start, value_range.end);
+            match options.inline_comments {
+                InlineCommentPolicy::None => {}
+                InlineCommentPolicy::Strip => {
+                    comment = Some(raw_value[comment_start..].to_string());
+                    raw_value = raw_value[..comment_start].trim_end();
+                }
+                InlineCommentPolicy::Warn => {
+                    warnings.push(SyntaxError { kind: SyntaxErrorKind::SuspiciousComment, span: comment_span });
+                }
+            }
+        }
+        let quoted = match unquote(raw_value) {
+            Ok(quoted) => quoted,
+            Err((kind, start, end)) => {
+                let offset = value_range.start;
+                errors.push(SyntaxError { kind, span: line.span(offset + start, offset + end) });
+                continue;
+            }
+        };
+        let split = options.split_keys.contains(&path.segments)
+            || (options.split_values && raw_value.contains(char::is_whitespace));
+        let value = if let Some(quoted) = quoted {
+            ConfigValue::String(quoted)
+        } else if split {
+            ConfigValue::Array(raw_value.split_whitespace().map(|v| scalar_value(v, options)).collect())
+        } else if raw_value.is_empty() {
+            ConfigValue::Empty
+        } else {
+            scalar_value(raw_value, options)
+        };
+        let span = line.span(key.start, key.end);
+        let entry = Entry { key: normalized_key, path, value, span, ignore_failure: node.ignore_failure, pattern: None, comment };
+        // グロブを含むキーは、明示的なキーをすべて読み込んでから展開する
+        if options.sysctl_root.is_some() && entry.path.segments.iter().any(|segment| is_glob(segment)) {
+            glob_entries.push(entry);
+            continue;
+        }
+        match insert_config_value(
+            &mut config,
+            &mut defined_at,
+            &entry.path,
+            entry.value.clone(),
+            span,
+            options.conflict_policy,
+        ) {
+            Ok(()) => entries.push(entry),
+            Err(conflict) => conflicts.push(conflict),
+        }
+    }
+
+    // systemd-sysctl と同じく、明示的に定義されたキーはグロブによる定義より優先する
+    if let Some(root) = &options.sysctl_root {
+        let explicit: HashSet<&[String]> = entries.iter().map(|entry| entry.path.segments.as_slice()).collect();
+        let mut expanded = Vec::new();
+        for glob_entry in glob_entries {
+            for segments in sysctld::expand_glob_key(root, &glob_entry.path.segments) {
+                if explicit.contains(segments.as_slice()) {
+                    continue;
+                }
+                // 展開してもセグメントの数は変わらないため、位置は展開元のキーのものを使う
+                let entry = Entry {
+                    key: format_key(&segments),
+                    path: KeyPath { segments, ends: glob_entry.path.ends.clone() },
+                    pattern: Some(glob_entry.key.clone()),
+                    ..glob_entry.clone()
+                };
+                match insert_config_value(
+                    &mut config,
+                    &mut defined_at,
+                    &entry.path,
+                    entry.value.clone(),
+                    entry.span,
+                    options.conflict_policy,
+                ) {
+                    Ok(()) => expanded.push(entry),
+                    Err(conflict) => conflicts.push(conflict),
+                }
+            }
+        }
+        entries.extend(expanded);
+    }
+
+    ParsedConfig { config, entries, dropped: errors, warnings, conflicts }
+}
+
+// 引用符の外にあり、空白の直後にある最初の `#`・`;` の位置を返す
+fn find_inline_comment(raw: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut prev_is_space = false;
+
+    for (i, c) in raw.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if i == 0 && (c == '"' || c == '\'') => quote = Some(c),
+            None if (c == '#' || c == ';') && prev_is_space => return Some(i),
+            None => {}
+        }
+        prev_is_space = c.is_whitespace();
+    }
+    None
+}
+
+// `"` または `'` で囲まれた値の引用符を外し、`\n`・`\t`・`\"`・`\'`・`\\` を展開する。
+// 引用符で始まらない値は None を返し、従来どおりそのまま扱う。
+// エラーの場合は種類と、値の先頭からのバイトオフセットで表した位置を返す
+fn unquote(raw: &str) -> Result<Option<String>, (SyntaxErrorKind, usize, usize)> {
+    let Some(quote) = raw.chars().next().filter(|&c| c == '"' || c == '\'') else {
+        return Ok(None);
+    };
+
+    let mut value = String::new();
+    let mut chars = raw.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let Some((_, escaped)) = chars.next() else {
+                    break;
+                };
+                value.push(match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    '"' | '\'' | '\\' => escaped,
+                    _ => return Err((SyntaxErrorKind::InvalidEscape(escaped), i, i + 1 + escaped.len_utf8())),
+                });
+            }
+            c if c == quote => {
+                let rest = &raw[i + c.len_utf8()..];
+                if !rest.trim().is_empty() {
+                    let start = raw.len() - rest.trim_start().len();
+                    return Err((SyntaxErrorKind::TrailingCharacters, start, raw.len()));
+                }
+                return Ok(Some(value));
+            }
+            c => value.push(c),
+        }
+    }
+    Err((SyntaxErrorKind::UnterminatedQuote, 0, 1))
+}
+
+// キーをセグメントに分割したもの。`ends` は各セグメントの終わりの、キーの先頭からのバイトオフセット
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyPath {
+    segments: Vec<String>,
+    ends: Vec<usize>,
+}
+
+// キーを区切り文字で分割する。区切り文字は `.` だが、sysctl と同じく最初の区切り文字が `/` のキーは
+// `/` で区切り、セグメント内の `.` を `/` に置き換える（`net/ipv4/conf/eth0.100/rp_filter` の
+// `eth0.100` は `eth0/100` になる）。`"my.host".port` のように `"` で囲んだセグメントは区切らない。
+// 空のセグメントは取り除き、その位置（区切り文字のオフセット）を返す。
+// エラーの場合は種類と、キーの先頭からのバイトオフセットで表した位置を返す
+fn parse_key_path(key: &str) -> Result<(KeyPath, Vec<usize>), (SyntaxErrorKind, usize, usize)> {
+    let mut in_quote = false;
+    let separator = key.chars()
+        .find(|&c| {
+            if c == '"' {
+                in_quote = !in_quote;
+            }
+            !in_quote && (c == '.' || c == '/')
+        })
+        .unwrap_or('.');
+
+    let mut path = KeyPath { segments: Vec::new(), ends: Vec::new() };
+    let mut empty_segments = Vec::new();
+    let mut segment = String::new();
+    let mut chars = key.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == separator {
+            if segment.is_empty() {
+                empty_segments.push(i);
+            } else {
+                path.segments.push(std::mem::take(&mut segment));
+                path.ends.push(i);
+            }
+        } else if c == '"' && segment.is_empty() {
+            let close = key[i + 1..].find('"')
+                .map(|j| i + 1 + j)
+                .ok_or((SyntaxErrorKind::UnterminatedQuote, i, i + 1))?;
+            segment.push_str(&key[i + 1..close]);
+            while chars.next_if(|&(j, _)| j <= close).is_some() {}
+            if let Some(&(j, next)) = chars.peek()
+                && next != separator
+            {
+                return Err((SyntaxErrorKind::InvalidKeyChar(next), j, j + next.len_utf8()));
+            }
+        } else if c == '"' {
+            return Err((SyntaxErrorKind::InvalidKeyChar(c), i, i + 1));
+        } else if separator == '/' && c == '.' {
+            segment.push('/');
+        } else {
+            segment.push(c);
+        }
+    }
+
+    if !segment.is_empty() {
+        path.segments.push(segment);
+        path.ends.push(key.len());
+    } else if let Some(last) = key.len().checked_sub(1)
+        && !empty_segments.contains(&last)
+    {
+        empty_segments.push(last);
+    }
+    Ok((path, empty_segments))
+}
+
+// キーのパスを表示用のドット区切りの文字列にする。区切り文字を含むセグメントは `"` で囲む
+fn format_key(segments: &[String]) -> String {
+    segments.iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            if segment.contains(['.', '"']) || (i == 0 && segment.contains('/')) {
+                format!("\"{}\"", segment)
+            } else {
+                segment.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn scalar_value(raw: &str, options: &ParseOptions) -> ConfigValue {
+    if options.infer_types {
+        infer_value(raw)
+    } else {
+        ConfigValue::String(raw.to_string())
+    }
+}
+
+// true/false は真偽値、10進・16進（0x）・8進（0o または先頭の0）は整数、
+// 小数・指数表記は浮動小数点数として解釈し、それ以外は文字列のまま残す
+fn infer_value(raw: &str) -> ConfigValue {
+    match raw {
+        "true" => return ConfigValue::Bool(true),
+        "false" => return ConfigValue::Bool(false),
+        _ => {}
+    }
+    if let Some(n) = parse_int(raw) {
+        return match i64::try_from(n) {
+            Ok(n) => ConfigValue::Int(n),
+            Err(_) => ConfigValue::UInt(n as u64),
+        };
+    }
+    if FLOAT_REGEX.is_match(raw)
+        && let Ok(f) = raw.parse::<f64>()
+        && f.is_finite()
+    {
+        return ConfigValue::Float(f);
+    }
+    ConfigValue::String(raw.to_string())
+}
+
+// i64 と u64 を合わせた範囲に収まる整数を返す
+fn parse_int(raw: &str) -> Option<i128> {
+    let (negative, digits) = match raw.as_bytes().first()? {
+        b'-' => (true, &raw[1..]),
+        b'+' => (false, &raw[1..]),
+        _ => (false, raw),
+    };
+    let (radix, digits) = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        (16, hex)
+    } else if let Some(oct) = digits.strip_prefix("0o").or_else(|| digits.strip_prefix("0O")) {
+        (8, oct)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
+    } else {
+        (10, digits)
+    };
+    // from_str_radix は符号を受け付けるため、符号の重複を防ぐ
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let magnitude = i128::from_str_radix(digits, radix).ok()?;
+    let n = if negative { -magnitude } else { magnitude };
+    (i128::from(i64::MIN)..=i128::from(u64::MAX)).contains(&n).then_some(n)
+}
+
+// `defined_at` にはキーのパス（`["a", "b"]` など）ごとに最初に定義された位置を記録する
+fn insert_config_value(
+    config: &mut IndexMap<String, ConfigValue>,
+    defined_at: &mut HashMap<Vec<String>, Span>,
+    key: &KeyPath,
+    value: ConfigValue,
+    span: Span,
+    policy: ConflictPolicy,
+) -> Result<(), KeyConflict> {
+    let keys = &key.segments;
     let mut map = config;
 
-    for sub_key in &keys[..keys.len() - 1] {
-        map = map.entry(sub_key.to_string())
-            .or_insert_with(|| ConfigValue::Map(HashMap::new()))
-            .as_map_mut()
-            .expect("型の不一致");
+    for (depth, sub_key) in keys[..keys.len() - 1].iter().enumerate() {
+        let path = keys[..=depth].to_vec();
+        let path_span = Span::new(span.line, span.start, span.start + key.ends[depth]);
+        let entry = map.entry(sub_key.clone())
+            .or_insert_with(|| ConfigValue::Map(IndexMap::new()));
+
+        if entry.as_map_mut().is_none() {
+            match policy {
+                ConflictPolicy::Error => {
+                    return Err(KeyConflict { defined: defined_at[&path], key: format_key(&path), span: path_span });
+                }
+                ConflictPolicy::LastWins => {
+                    *entry = ConfigValue::Map(IndexMap::new());
+                    defined_at.insert(path.clone(), path_span);
+                }
+                ConflictPolicy::KeepScalar => {
+                    let scalar = std::mem::replace(entry, ConfigValue::Map(IndexMap::new()));
+                    let scalar_path = [path.as_slice(), &[SCALAR_VALUE_KEY.to_string()]].concat();
+                    defined_at.insert(scalar_path, defined_at[&path]);
+                    if let Some(m) = entry.as_map_mut() {
+                        m.insert(SCALAR_VALUE_KEY.to_string(), scalar);
+                    }
+                }
+            }
+        }
+        defined_at.entry(path).or_insert(path_span);
+
+        map = match entry {
+            ConfigValue::Map(m) => m,
+            _ => unreachable!(),
+        };
+    }
+
+    let last_key = keys.last().unwrap().clone();
+    if let Some(existing) = map.get_mut(&last_key).and_then(ConfigValue::as_map_mut) {
+        match policy {
+            ConflictPolicy::Error => {
+                return Err(KeyConflict { defined: defined_at[keys], key: format_key(keys), span });
+            }
+            ConflictPolicy::LastWins => {}
+            ConflictPolicy::KeepScalar => {
+                existing.insert(SCALAR_VALUE_KEY.to_string(), value);
+                defined_at.insert([keys.as_slice(), &[SCALAR_VALUE_KEY.to_string()]].concat(), span);
+                return Ok(());
+            }
+        }
+    }
+
+    map.insert(last_key, value);
+    defined_at.insert(keys.clone(), span);
+    Ok(())
+}
+
+// `max_depth` は引数のディレクトリから何階層下のファイルまでを対象にするか（直下が1）
+// `include`・`exclude`・`default_ignores` はディレクトリ内のエントリ名にだけ適用し、
+// 引数で直接指定したファイルには適用しない
+#[derive(Debug, Clone)]
+struct CollectOptions {
+    recursive: bool,
+    max_depth: Option<usize>,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    default_ignores: bool,
+}
+
+impl Default for CollectOptions {
+    fn default() -> Self {
+        CollectOptions {
+            recursive: false,
+            max_depth: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            default_ignores: true,
+        }
+    }
+}
+
+impl CollectOptions {
+    fn is_excluded(&self, name: &str) -> bool {
+        self.exclude.iter().any(|glob| glob.is_match(name))
+            || (self.default_ignores && DEFAULT_IGNORE_GLOBS.iter().any(|glob| glob.is_match(name)))
+    }
+
+    fn is_included(&self, name: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(name))
+    }
+}
+
+#[derive(Debug)]
+enum PathError {
+    Io(io::Error),
+    Unsupported,
+    SymlinkLoop,
+}
+
+impl From<io::Error> for PathError {
+    fn from(e: io::Error) -> Self {
+        PathError::Io(e)
     }
+}
 
-    map.insert(keys.last().unwrap().to_string(), value);
+impl PathError {
+    fn message(&self, lang: Lang) -> String {
+        match (self, lang) {
+            (PathError::Io(e), _) => e.to_string(),
+            (PathError::Unsupported, Lang::Ja) => "通常のファイルまたはディレクトリではありません".to_string(),
+            (PathError::Unsupported, Lang::En) => "not a regular file or directory".to_string(),
+            (PathError::SymlinkLoop, Lang::Ja) => "シンボリックリンクがループしています".to_string(),
+            (PathError::SymlinkLoop, Lang::En) => "symbolic link loop detected".to_string(),
+        }
+    }
 }
 
-fn collect_text_files(path: &Path) -> io::Result<Vec<PathBuf>> {
-    if path.is_file() {
-        return Ok(vec![path.to_path_buf()]);
+// 見つかったファイルを辞書順で返し、読み込めなかったパスは `errors` に追加する
+fn collect_text_files(
+    path: &Path,
+    options: &CollectOptions,
+    errors: &mut Vec<(PathBuf, PathError)>,
+) -> Vec<PathBuf> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            errors.push((path.to_path_buf(), e.into()));
+            return Vec::new();
+        }
+    };
+    if metadata.is_file() {
+        return vec![path.to_path_buf()];
     }
-    if path.is_dir() {
-        return Ok(fs::read_dir(path)?
+    if !metadata.is_dir() {
+        errors.push((path.to_path_buf(), PathError::Unsupported));
+        return Vec::new();
+    }
+
+    let mut files = Vec::new();
+    walk_dir(path, 1, options, &mut Vec::new(), &mut files, errors);
+    files
+}
+
+// `ancestors` には辿ってきたディレクトリの実パスを保持し、シンボリックリンクのループを検出する
+fn walk_dir(
+    dir: &Path,
+    depth: usize,
+    options: &CollectOptions,
+    ancestors: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    errors: &mut Vec<(PathBuf, PathError)>,
+) {
+    let entries = fs::canonicalize(dir).and_then(|real_path| {
+        let entries = fs::read_dir(dir)?
             .filter_map(Result::ok)
             .map(|entry| entry.path())
-            .filter(|p| p.is_file())
-            .collect());
+            .collect::<Vec<_>>();
+        Ok((real_path, entries))
+    });
+    let (real_path, mut entries) = match entries {
+        Ok(found) => found,
+        Err(e) => {
+            errors.push((dir.to_path_buf(), e.into()));
+            return;
+        }
+    };
+    if ancestors.contains(&real_path) {
+        errors.push((dir.to_path_buf(), PathError::SymlinkLoop));
+        return;
     }
-    Err(io::Error::new(io::ErrorKind::NotFound, "パスが見つかりません"))
+
+    entries.sort();
+    ancestors.push(real_path);
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if options.is_excluded(&name) {
+            continue;
+        }
+        if entry.is_file() {
+            if options.is_included(&name) {
+                files.push(entry);
+            }
+        } else if entry.is_dir()
+            && options.recursive
+            && options.max_depth.is_none_or(|max_depth| depth < max_depth)
+        {
+            walk_dir(&entry, depth + 1, options, ancestors, files, errors);
+        }
+    }
+    ancestors.pop();
 }
 
-fn get_text_files(args: &[String]) -> Vec<PathBuf> {
-    if args.is_empty() {
-        eprintln!("ファイルを指定してください。");
-        std::process::exit(1);
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_USAGE);
+}
+
+fn option_value<'a>(args: &mut impl Iterator<Item = &'a OsString>, name: &str) -> &'a str {
+    match args.next() {
+        Some(value) => value.to_str()
+            .unwrap_or_else(|| usage_error(&format!("{} の値が UTF-8 ではありません。", name))),
+        None => usage_error(&format!("{} の値を指定してください。", name)),
     }
+}
 
-    args.iter()
-        .flat_map(|arg| collect_text_files(Path::new(arg)).unwrap_or_default())
-        .collect()
+fn parse_conflict_policy(value: &str) -> ConflictPolicy {
+    match value {
+        "error" => ConflictPolicy::Error,
+        "last-wins" => ConflictPolicy::LastWins,
+        "keep-scalar" => ConflictPolicy::KeepScalar,
+        _ => usage_error(&format!("--on-conflict には error, last-wins, keep-scalar のいずれかを指定してください: {}", value)),
+    }
+}
+
+fn parse_inline_comment_policy(value: &str) -> InlineCommentPolicy {
+    match value {
+        "none" => InlineCommentPolicy::None,
+        "strip" => InlineCommentPolicy::Strip,
+        "warn" => InlineCommentPolicy::Warn,
+        _ => usage_error(&format!("--inline-comments には none, strip, warn のいずれかを指定してください: {}", value)),
+    }
+}
+
+fn parse_lang(value: &str) -> Lang {
+    match value {
+        "ja" => Lang::Ja,
+        "en" => Lang::En,
+        _ => usage_error(&format!("--lang には ja, en のいずれかを指定してください: {}", value)),
+    }
+}
+
+// Text: ファイルごとに見出しと整形済みJSONを出力する
+// Object: ファイルパスをキーとする1つのJSONオブジェクトを出力する
+// Records: `{path, config, errors}` の配列を出力する
+// Ndjson: `{path, config, errors}` を1ファイル1行で出力する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputMode {
+    Text,
+    Object,
+    Records,
+    Ndjson,
+}
+
+fn parse_output_mode(value: &str) -> OutputMode {
+    match value {
+        "text" => OutputMode::Text,
+        "object" => OutputMode::Object,
+        "records" => OutputMode::Records,
+        "ndjson" => OutputMode::Ndjson,
+        _ => usage_error(&format!("--output には text, object, records, ndjson のいずれかを指定してください: {}", value)),
+    }
 }
 
-fn format_as_json(config: &HashMap<String, ConfigValue>) -> serde_json::Value {
+#[derive(Debug, Clone)]
+struct Options {
+    collect: CollectOptions,
+    parse: ParseOptions,
+    format: FormatOptions,
+    output: OutputMode,
+    lang: Lang,
+    merge: bool,
+    systemd: bool,
+    root: PathBuf,
+}
+
+fn parse_args(args: &[OsString]) -> (Options, Vec<PathBuf>) {
+    let mut options = Options {
+        collect: CollectOptions::default(),
+        parse: ParseOptions::default(),
+        format: FormatOptions::default(),
+        output: OutputMode::Text,
+        lang: Lang::Ja,
+        merge: false,
+        systemd: false,
+        root: PathBuf::from("/"),
+    };
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let Some(arg_str) = arg.to_str() else {
+            paths.push(PathBuf::from(arg));
+            continue;
+        };
+        match arg_str {
+            "--recursive" => options.collect.recursive = true,
+            "--max-depth" => {
+                let value = option_value(&mut args, "--max-depth");
+                let depth = value.parse().ok().filter(|&depth| depth >= 1).unwrap_or_else(|| {
+                    usage_error(&format!("--max-depth には1以上の整数を指定してください: {}", value))
+                });
+                options.collect.recursive = true;
+                options.collect.max_depth = Some(depth);
+            }
+            "--include" | "--exclude" => {
+                let value = option_value(&mut args, arg_str);
+                let glob = Glob::new(value)
+                    .unwrap_or_else(|e| usage_error(&format!("{} のパターンが不正です: {} ({})", arg_str, value, e)));
+                if arg_str == "--include" {
+                    options.collect.include.push(glob);
+                } else {
+                    options.collect.exclude.push(glob);
+                }
+            }
+            "--no-default-ignores" => options.collect.default_ignores = false,
+            "--strict" => options.parse.mode = ParseMode::Strict,
+            "--lenient" => options.parse.mode = ParseMode::Lenient,
+            "--partial" => options.parse.mode = ParseMode::Partial,
+            "--on-conflict" => {
+                let value = option_value(&mut args, "--on-conflict");
+                options.parse.conflict_policy = parse_conflict_policy(value);
+            }
+            "--inline-comments" => {
+                let value = option_value(&mut args, "--inline-comments");
+                options.parse.inline_comments = parse_inline_comment_policy(value);
+            }
+            "--raw-strings" => options.parse.infer_types = false,
+            "--normalize-keys" => options.parse.normalize_keys = true,
+            "--split-values" => options.parse.split_values = true,
+            "--split-key" => {
+                let value = option_value(&mut args, "--split-key");
+                options.parse.split_keys.push(parse_cli_key(value));
+            }
+            "--sort-keys" => options.format.sort_keys = true,
+            "--empty-as-null" => options.format.empty_as_null = true,
+            "--output" => {
+                let value = option_value(&mut args, "--output");
+                options.output = parse_output_mode(value);
+            }
+            "--merge" => options.merge = true,
+            "--provenance" => options.format.provenance = true,
+            "--show-ignore-failure" => options.format.ignore_failure = true,
+            "--sysctl-root" => {
+                options.parse.sysctl_root = Some(PathBuf::from(option_value(&mut args, "--sysctl-root")));
+            }
+            "--systemd" => options.systemd = true,
+            "--root" => options.root = PathBuf::from(option_value(&mut args, "--root")),
+            "--lang" => {
+                let value = option_value(&mut args, "--lang");
+                options.lang = parse_lang(value);
+            }
+            _ if arg_str.starts_with("--") => usage_error(&format!("不明なオプションです: {}", arg_str)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    (options, paths)
+}
+
+fn report_diagnostics(file_path: &Path, source: &str, diagnostics: &[Diagnostic], lang: Lang) {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(file_path, source, lang));
+    }
+}
+
+// 読み込めなかったパスは標準エラー出力に報告し、その件数を返す
+// シンボリックリンクのループは辿らずに警告するだけで、失敗には数えない
+fn get_text_files(paths: &[PathBuf], options: &CollectOptions, lang: Lang) -> (Vec<PathBuf>, usize) {
+    if paths.is_empty() {
+        usage_error("ファイルを指定してください。");
+    }
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        files.extend(collect_text_files(path, options, &mut errors));
+    }
+
+    let mut failures = 0;
+    for (path, error) in &errors {
+        report_path_error(path, error, lang);
+        if !matches!(error, PathError::SymlinkLoop) {
+            failures += 1;
+        }
+    }
+    (files, failures)
+}
+
+fn report_path_error(path: &Path, error: &PathError, lang: Lang) {
+    let message = error.message(lang);
+    match (error, lang) {
+        (PathError::SymlinkLoop, Lang::Ja) => eprintln!("警告: スキップしました: {} ({})", path.display(), message),
+        (PathError::SymlinkLoop, Lang::En) => eprintln!("warning: skipped: {} ({})", path.display(), message),
+        (_, Lang::Ja) => eprintln!("パスを読み込めません: {} ({})", path.display(), message),
+        (_, Lang::En) => eprintln!("cannot read path: {} ({})", path.display(), message),
+    }
+}
+
+// `sort_keys` が false の場合はファイル内の出現順でキーを出力する
+// `provenance` が true の場合は設定と並べて、各キーの定義位置を出力する
+// `ignore_failure` が true の場合は設定と並べて、`-` 付きで定義されたキーの一覧を出力する
+// `empty_as_null` が true の場合は値のない定義（`key =`）を空文字列ではなく null として出力する
+#[derive(Debug, Clone, Default)]
+struct FormatOptions {
+    sort_keys: bool,
+    provenance: bool,
+    ignore_failure: bool,
+    empty_as_null: bool,
+}
+
+fn format_as_json(config: &IndexMap<String, ConfigValue>, options: &FormatOptions) -> serde_json::Value {
+    let mut entries: Vec<_> = config.iter().collect();
+    if options.sort_keys {
+        entries.sort_by_key(|(key, _)| *key);
+    }
+
     let mut json_obj = serde_json::Map::new();
-    for (key, value) in config {
-        match value {
-            ConfigValue::String(s) => {
-                json_obj.insert(key.clone(), json!(s));
+    for (key, value) in entries {
+        json_obj.insert(key.clone(), format_value(value, options));
+    }
+    serde_json::Value::Object(json_obj)
+}
+
+// キーごとに、最終的に採用された定義（`origin`）と上書きされた定義（`shadowed`）を出力する。
+// `sources` は適用した順に並んだファイルとその定義の一覧
+fn format_provenance(
+    config: &IndexMap<String, ConfigValue>,
+    sources: &[(&Path, &[Entry])],
+    options: &FormatOptions,
+) -> serde_json::Value {
+    let mut definitions: IndexMap<&str, (&[String], Vec<serde_json::Value>)> = IndexMap::new();
+    for (path, entries) in sources {
+        for entry in entries.iter() {
+            let mut definition = json!({
+                "file": path.display().to_string(),
+                "line": entry.span.line,
+                "value": format_value(&entry.value, options),
+            });
+            if entry.ignore_failure {
+                definition["ignore_failure"] = json!(true);
             }
-            ConfigValue::Map(m) => {
-                json_obj.insert(key.clone(), format_as_json(m));
+            if let Some(pattern) = &entry.pattern {
+                definition["pattern"] = json!(pattern);
             }
+            if let Some(comment) = &entry.comment {
+                definition["comment"] = json!(comment);
+            }
+            definitions.entry(entry.key.as_str())
+                .or_insert_with(|| (&entry.path.segments, Vec::new()))
+                .1
+                .push(definition);
         }
     }
+    if options.sort_keys {
+        definitions.sort_keys();
+    }
+
+    let mut json_obj = serde_json::Map::new();
+    for (key, (path, mut defined)) in definitions {
+        // 後から別の型の定義で置き換えられたキーには、採用された定義がない。
+        // 明示的な定義がある場合は、グロブを展開して得た定義より優先される
+        let origin = match lookup_scalar(config, path) {
+            None => serde_json::Value::Null,
+            Some(_) => {
+                let index = defined.iter()
+                    .rposition(|definition| definition.get("pattern").is_none())
+                    .unwrap_or(defined.len() - 1);
+                defined.remove(index)
+            }
+        };
+        json_obj.insert(key.to_string(), json!({ "origin": origin, "shadowed": defined }));
+    }
     serde_json::Value::Object(json_obj)
 }
 
+// 最終的に採用された定義が `-` 付きだったキーの一覧を出力する
+fn format_ignore_failure(
+    config: &IndexMap<String, ConfigValue>,
+    sources: &[(&Path, &[Entry])],
+    options: &FormatOptions,
+) -> serde_json::Value {
+    let mut flags: IndexMap<&str, (&[String], bool)> = IndexMap::new();
+    for (_, entries) in sources {
+        for entry in entries.iter() {
+            flags.insert(entry.key.as_str(), (&entry.path.segments, entry.ignore_failure));
+        }
+    }
+    if options.sort_keys {
+        flags.sort_keys();
+    }
+
+    let keys: Vec<_> = flags.into_iter()
+        .filter(|(_, (path, ignore_failure))| {
+            *ignore_failure && lookup_scalar(config, path).is_some()
+        })
+        .map(|(key, _)| key)
+        .collect();
+    json!(keys)
+}
+
+fn config_output(
+    config: &IndexMap<String, ConfigValue>,
+    sources: &[(&Path, &[Entry])],
+    options: &FormatOptions,
+) -> serde_json::Value {
+    let config_json = format_as_json(config, options);
+    if !options.provenance && !options.ignore_failure {
+        return config_json;
+    }
+
+    let mut output = json!({ "config": config_json });
+    if options.provenance {
+        output["provenance"] = format_provenance(config, sources, options);
+    }
+    if options.ignore_failure {
+        output["ignore_failure"] = format_ignore_failure(config, sources, options);
+    }
+    output
+}
+
+// キーに定義したスカラー値が残っていれば返す。`--on-conflict keep-scalar` でマップに変わったキーは、
+// 子キー `_value` に残したスカラー値を返す
+fn lookup_scalar<'a>(config: &'a IndexMap<String, ConfigValue>, path: &[String]) -> Option<&'a ConfigValue> {
+    match lookup_config(config, path)? {
+        ConfigValue::Map(m) => m.get(SCALAR_VALUE_KEY).filter(|value| !matches!(value, ConfigValue::Map(_))),
+        value => Some(value),
+    }
+}
+
+fn lookup_config<'a>(config: &'a IndexMap<String, ConfigValue>, path: &[String]) -> Option<&'a ConfigValue> {
+    let (first, rest) = path.split_first()?;
+    match (config.get(first)?, rest) {
+        (value, []) => Some(value),
+        (ConfigValue::Map(m), rest) => lookup_config(m, rest),
+        _ => None,
+    }
+}
+
+fn format_value(value: &ConfigValue, options: &FormatOptions) -> serde_json::Value {
+    match value {
+        ConfigValue::Empty if options.empty_as_null => serde_json::Value::Null,
+        ConfigValue::Empty => json!(""),
+        ConfigValue::String(s) => json!(s),
+        ConfigValue::Bool(b) => json!(b),
+        ConfigValue::Int(n) => json!(n),
+        ConfigValue::UInt(n) => json!(n),
+        ConfigValue::Float(f) => json!(f),
+        ConfigValue::Array(values) => {
+            serde_json::Value::Array(values.iter().map(|v| format_value(v, options)).collect())
+        }
+        ConfigValue::Map(m) => format_as_json(m, options),
+    }
+}
+
+// 1ファイル分の処理結果。`config` は読み込みや解析に失敗した場合に None となる
+struct FileReport {
+    path: PathBuf,
+    config: Option<IndexMap<String, ConfigValue>>,
+    entries: Vec<Entry>,
+    errors: Vec<serde_json::Value>,
+}
+
+impl FileReport {
+    fn to_json(&self, options: &FormatOptions) -> serde_json::Value {
+        let mut record = json!({
+            "path": self.path.display().to_string(),
+            "config": self.config.as_ref().map(|c| format_as_json(c, options)),
+            "errors": self.errors,
+        });
+        if options.provenance {
+            record["provenance"] = self.config.as_ref()
+                .map(|c| format_provenance(c, &[(&self.path, &self.entries)], options))
+                .unwrap_or_default();
+        }
+        if options.ignore_failure {
+            record["ignore_failure"] = self.config.as_ref()
+                .map(|c| format_ignore_failure(c, &[(&self.path, &self.entries)], options))
+                .unwrap_or_default();
+        }
+        record
+    }
+
+    fn config_output(&self, options: &FormatOptions) -> serde_json::Value {
+        self.config.as_ref()
+            .map(|c| config_output(c, &[(&self.path, &self.entries)], options))
+            .unwrap_or_default()
+    }
+}
+
+fn process_file(file_path: &Path, options: &Options) -> FileReport {
+    let mut report = FileReport {
+        path: file_path.to_path_buf(),
+        config: None,
+        entries: Vec::new(),
+        errors: Vec::new(),
+    };
+    let source = match fs::read_to_string(file_path) {
+        Ok(source) => source,
+        Err(e) => {
+            let message = match (e.kind(), options.lang) {
+                (io::ErrorKind::InvalidData, Lang::Ja) => "UTF-8 のテキストとして読み込めません".to_string(),
+                (io::ErrorKind::InvalidData, Lang::En) => "file is not valid UTF-8 text".to_string(),
+                _ => e.to_string(),
+            };
+            match options.lang {
+                Lang::Ja => eprintln!("ファイルの読み込みエラー: {} ({})", message, file_path.display()),
+                Lang::En => eprintln!("failed to read file: {} ({})", message, file_path.display()),
+            }
+            report.errors.push(json!({ "severity": "error", "code": "read-error", "message": message }));
+            return report;
+        }
+    };
+
+    let result = match options.parse.mode {
+        ParseMode::Partial => Ok(parse_config_partial(&source, &options.parse)),
+        _ => parse_config_str(&source, &options.parse),
+    };
+    let diagnostics: Vec<Diagnostic> = match result {
+        Ok(parsed) if options.parse.mode == ParseMode::Partial => {
+            let diagnostics = parsed.diagnostics(options.lang);
+            report.config = Some(parsed.config);
+            report.entries = parsed.entries;
+            diagnostics
+        }
+        Ok(parsed) => {
+            if !parsed.dropped.is_empty() {
+                match options.lang {
+                    Lang::Ja => eprintln!("文法エラーの行を読み飛ばしました ({})", file_path.display()),
+                    Lang::En => eprintln!("ignored lines with syntax errors ({})", file_path.display()),
+                }
+            }
+            report.config = Some(parsed.config);
+            report.entries = parsed.entries;
+            parsed.dropped.iter()
+                .chain(&parsed.warnings)
+                .map(|e| e.to_diagnostic(Severity::Warning, options.lang))
+                .collect()
+        }
+        Err(ParseError::Syntax(errors)) => {
+            match options.lang {
+                Lang::Ja => eprintln!("文法エラーのためファイルをスキップしました ({})", file_path.display()),
+                Lang::En => eprintln!("skipped file due to syntax errors ({})", file_path.display()),
+            }
+            errors.iter()
+                .map(|e| e.to_diagnostic(Severity::Error, options.lang))
+                .collect()
+        }
+        Err(ParseError::Conflict(conflicts)) => {
+            match options.lang {
+                Lang::Ja => eprintln!("キーの衝突のためファイルをスキップしました ({})", file_path.display()),
+                Lang::En => eprintln!("skipped file due to key conflicts ({})", file_path.display()),
+            }
+            conflicts.iter().map(|c| c.to_diagnostic(options.lang)).collect()
+        }
+    };
+
+    report_diagnostics(file_path, &source, &diagnostics, options.lang);
+    report.errors = diagnostics.iter().map(|d| d.to_json(&source)).collect();
+    report
+}
+
+// 後から読み込んだ設定で上書きする。両方がマップのキーは再帰的にマージする。
+// スカラー値とマップが衝突したときは、ファイル内の衝突と同じく `policy` に従う。
+// `ConflictPolicy::Error` の場合は衝突したキーのパスを返す（`target` は途中まで書き換えられている）
+fn merge_config(
+    target: &mut IndexMap<String, ConfigValue>,
+    source: IndexMap<String, ConfigValue>,
+    policy: ConflictPolicy,
+) -> Result<(), Vec<String>> {
+    for (key, value) in source {
+        let prefix_error = |mut path: Vec<String>| {
+            path.insert(0, key.clone());
+            path
+        };
+        match (target.get_mut(&key), value, policy) {
+            (Some(ConfigValue::Map(target_map)), ConfigValue::Map(source_map), _) => {
+                merge_config(target_map, source_map, policy).map_err(prefix_error)?;
+            }
+            (Some(ConfigValue::Map(_)), _, ConflictPolicy::Error)
+            | (Some(_), ConfigValue::Map(_), ConflictPolicy::Error) => return Err(vec![key]),
+            (Some(ConfigValue::Map(target_map)), value, ConflictPolicy::KeepScalar) => {
+                target_map.insert(SCALAR_VALUE_KEY.to_string(), value);
+            }
+            (Some(scalar), ConfigValue::Map(source_map), ConflictPolicy::KeepScalar) => {
+                let mut map = IndexMap::new();
+                map.insert(SCALAR_VALUE_KEY.to_string(), scalar.clone());
+                merge_config(&mut map, source_map, policy).map_err(prefix_error)?;
+                *scalar = ConfigValue::Map(map);
+            }
+            (_, value, _) => {
+                target.insert(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+// マージした設定と各ファイルの定義
+struct MergedConfig {
+    config: IndexMap<String, ConfigValue>,
+    sources: Vec<(PathBuf, Vec<Entry>)>,
+    successes: usize,
+    failures: usize,
+}
+
+// ファイルを順に読み込んでマージした結果を1つのJSONとして出力する
+fn run_merged(files: Vec<PathBuf>, failures: usize, options: &Options) -> i32 {
+    let merged = merge_files(files, failures, options);
+    let sources: Vec<_> = merged.sources.iter().map(|(path, entries)| (path.as_path(), entries.as_slice())).collect();
+    let json_output = config_output(&merged.config, &sources, &options.format);
+    println!("{}", serde_json::to_string_pretty(&json_output).unwrap());
+    exit_code(merged.successes, merged.failures)
+}
+
+fn merge_files(files: Vec<PathBuf>, mut failures: usize, options: &Options) -> MergedConfig {
+    let mut parsed = Vec::new();
+    for file_path in files {
+        let report = process_file(&file_path, options);
+        match report.config {
+            Some(config) => parsed.push((report.path, config, report.entries)),
+            None => failures += 1,
+        }
+    }
+
+    // 別のファイルで明示的に定義されたキーは、グロブを展開して得た値で上書きしない
+    let explicit: HashSet<String> = parsed.iter()
+        .flat_map(|(_, _, entries)| entries.iter())
+        .filter(|entry| entry.pattern.is_none())
+        .map(|entry| entry.key.clone())
+        .collect();
+
+    let mut successes = parsed.len();
+    let mut merged = IndexMap::new();
+    let mut sources = Vec::new();
+    for (path, mut config, entries) in parsed {
+        for entry in &entries {
+            if entry.pattern.is_some() && explicit.contains(&entry.key) {
+                remove_config_key(&mut config, &entry.path.segments);
+            }
+        }
+        // 衝突したファイルは、ファイル内で衝突した場合と同じくスキップする
+        let mut candidate = merged.clone();
+        if let Err(key) = merge_config(&mut candidate, config, options.parse.conflict_policy) {
+            match options.lang {
+                Lang::Ja => eprintln!(
+                    "キー `{}` が前のファイルと値とマップの両方として定義されているため、ファイルをスキップしました ({})",
+                    format_key(&key), path.display()
+                ),
+                Lang::En => eprintln!(
+                    "skipped file because key `{}` is defined both as a value and as a map across files ({})",
+                    format_key(&key), path.display()
+                ),
+            }
+            successes -= 1;
+            failures += 1;
+            continue;
+        }
+        merged = candidate;
+        sources.push((path, entries));
+    }
+    MergedConfig { config: merged, sources, successes, failures }
+}
+
+// キーを削除し、空になった親のマップも取り除く
+fn remove_config_key(config: &mut IndexMap<String, ConfigValue>, path: &[String]) {
+    match path {
+        [] => {}
+        [key] => {
+            config.shift_remove(key);
+        }
+        [first, rest @ ..] => {
+            if let Some(ConfigValue::Map(m)) = config.get_mut(first) {
+                remove_config_key(m, rest);
+                if m.is_empty() {
+                    config.shift_remove(first);
+                }
+            }
+        }
+    }
+}
+
+// systemd-sysctl と同じ順序で sysctl.d 以下の設定ファイルを読み込み、マージした結果を出力する
+fn run_systemd(options: &Options, paths: &[PathBuf]) -> i32 {
+    if !paths.is_empty() {
+        usage_error("--systemd を指定した場合はファイルを指定できません。");
+    }
+
+    let (files, failures) = systemd_files(options);
+    run_merged(files, failures, options)
+}
+
+fn systemd_files(options: &Options) -> (Vec<PathBuf>, usize) {
+    let (files, errors) = sysctld::config_files(&options.root);
+    let failures = errors.len();
+    for (path, error) in errors {
+        report_path_error(&path, &PathError::Io(error), options.lang);
+    }
+    (files, failures)
+}
+
+// `get` の引数から、オプション・キー・ファイル・`--default` の値を取り出す。
+// オプションはキーの前後どちらにも書ける
+fn parse_get_args(args: &[OsString]) -> (Options, String, Vec<PathBuf>, Option<String>) {
+    let mut default = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--default" {
+            default = Some(option_value(&mut args, "--default").to_string());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    let (options, mut paths) = parse_args(&rest);
+    if paths.is_empty() {
+        usage_error("使い方: get [--default VALUE] KEY [FILES...]");
+    }
+    let key = paths.remove(0).into_os_string().into_string()
+        .unwrap_or_else(|_| usage_error("get のキーが UTF-8 ではありません。"));
+    (options, key, paths, default)
+}
+
+// `get KEY [FILES...]`: 指定したファイルをマージした設定から1つのキーを引いて出力する。
+// ファイルを指定しない場合は `--systemd` と同じファイルを読み込む。
+// 値がスカラーの場合はそのまま、マップや配列の場合はJSONとして出力する
+fn run_get(args: &[OsString]) -> i32 {
+    let (options, key, paths, default) = parse_get_args(args);
+    let path = parse_cli_key(&key);
+    let (files, failures) = if paths.is_empty() {
+        systemd_files(&options)
+    } else {
+        get_text_files(&paths, &options.collect, options.lang)
+    };
+    let merged = merge_files(files, failures, &options);
+
+    let value = lookup_config(&merged.config, &path).map(|value| format_value(value, &options.format));
+    let output = match (value, default) {
+        (Some(value), _) => value,
+        (None, Some(default)) => json!(default),
+        (None, None) if merged.successes == 0 && merged.failures > 0 => return EXIT_FAILURE,
+        (None, None) => {
+            match options.lang {
+                Lang::Ja => eprintln!("キー `{}` は定義されていません", key),
+                Lang::En => eprintln!("key `{}` is not defined", key),
+            }
+            return EXIT_KEY_NOT_FOUND;
+        }
+    };
+    match output {
+        serde_json::Value::String(s) => println!("{}", s),
+        serde_json::Value::Null => println!(),
+        value @ (serde_json::Value::Array(_) | serde_json::Value::Object(_)) => {
+            println!("{}", serde_json::to_string_pretty(&value).unwrap());
+        }
+        value => println!("{}", value),
+    }
+    exit_code(merged.successes, merged.failures)
+}
+
+// `set KEY=VALUE FILE` と `unset KEY FILE` のオプション
+// `backup` が true の場合は、書き換える前のファイルを `FILE.bak` として残す
+// `inline_comments` が `Strip` の場合は、値を置き換えるときに行内コメントを残す
+#[derive(Debug, Clone)]
+struct EditOptions {
+    backup: bool,
+    inline_comments: InlineCommentPolicy,
+    lang: Lang,
+}
+
+fn run_edit(command: &str, args: &[OsString]) -> i32 {
+    let mut options = EditOptions { backup: false, inline_comments: InlineCommentPolicy::None, lang: Lang::Ja };
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--backup") => options.backup = true,
+            Some("--inline-comments") => {
+                let value = option_value(&mut args, "--inline-comments");
+                options.inline_comments = parse_inline_comment_policy(value);
+            }
+            Some("--lang") => {
+                let value = option_value(&mut args, "--lang");
+                options.lang = parse_lang(value);
+            }
+            Some(arg_str) if arg_str.starts_with("--") => usage_error(&format!("不明なオプションです: {}", arg_str)),
+            _ => positional.push(arg),
+        }
+    }
+    let [target, file_path] = positional[..] else {
+        match command {
+            "set" => usage_error("使い方: set [--backup] KEY=VALUE FILE"),
+            _ => usage_error("使い方: unset [--backup] KEY FILE"),
+        }
+    };
+    let target = target.to_str()
+        .unwrap_or_else(|| usage_error(&format!("{} の引数が UTF-8 ではありません。", command)));
+    let (key, value) = match command {
+        "set" => match target.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value)),
+            None => usage_error(&format!("KEY=VALUE の形式で指定してください: {}", target)),
+        },
+        _ => (target.trim(), None),
+    };
+    let path = parse_cli_key(key);
+    let file_path = Path::new(file_path);
+
+    let source = match fs::read_to_string(file_path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && value.is_some() => String::new(),
+        Err(e) => {
+            report_path_error(file_path, &PathError::Io(e), options.lang);
+            return EXIT_FAILURE;
+        }
+    };
+    let mut document = Document::parse(&source);
+    let found = match value {
+        Some(value) => set_key(&mut document, key, &path, value, &options),
+        None => unset_key(&mut document, &path),
+    };
+    if !found && value.is_none() {
+        match options.lang {
+            Lang::Ja => eprintln!("キー `{}` は定義されていません ({})", key, file_path.display()),
+            Lang::En => eprintln!("key `{}` is not defined ({})", key, file_path.display()),
+        }
+        return EXIT_KEY_NOT_FOUND;
+    }
+
+    if let Err(e) = write_atomically(file_path, &document.to_string(), options.backup) {
+        match options.lang {
+            Lang::Ja => eprintln!("ファイルの書き込みエラー: {} ({})", e, file_path.display()),
+            Lang::En => eprintln!("failed to write file: {} ({})", e, file_path.display()),
+        }
+        return EXIT_FAILURE;
+    }
+    EXIT_SUCCESS
+}
+
+// コマンドラインで指定されたキーを、ファイルに書いたときと同じ規則で分割する
+fn parse_cli_key(key: &str) -> Vec<String> {
+    cli_key_path(key).unwrap_or_else(|| usage_error(&format!("キーが不正です: {}", key)))
+}
+
+fn cli_key_path(key: &str) -> Option<Vec<String>> {
+    let valid = !key.is_empty() && matches!(
+        Document::parse(&format!("{} = 0", key)).lines.as_slice(),
+        [line] if matches!(&line.kind, LineKind::Entry(node) if line.text[node.key.clone()] == *key)
+    );
+    if !valid {
+        return None;
+    }
+    parse_key_path(key).ok()
+        .filter(|(path, empty)| empty.is_empty() && !path.segments.is_empty())
+        .map(|(path, _)| path.segments)
+}
+
+// キーが `path` と一致する定義行の位置と、その行の構文ノードを返す
+fn find_key_lines(document: &Document, path: &[String]) -> Vec<(usize, cst::EntryNode)> {
+    document.lines.iter()
+        .enumerate()
+        .filter_map(|(index, line)| match &line.kind {
+            LineKind::Entry(node) => parse_key_path(&line.text[node.key.clone()])
+                .ok()
+                .filter(|(key_path, _)| key_path.segments == path)
+                .map(|_| (index, node.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+// キーが定義されている行の値をすべて置き換え、定義されていなければ末尾に追加する。
+// 定義されていた場合は true を返す
+fn set_key(document: &mut Document, key: &str, path: &[String], value: &str, options: &EditOptions) -> bool {
+    let value = format_raw_value(value);
+    let lines = find_key_lines(document, path);
+    for (index, node) in &lines {
+        let text = &document.lines[*index].text;
+        let mut end = node.value.end;
+        if options.inline_comments == InlineCommentPolicy::Strip
+            && let Some(comment_start) = find_inline_comment(&text[node.value.clone()])
+        {
+            end = node.value.start + text[node.value.start..node.value.start + comment_start].trim_end().len();
+        }
+        // `key =1` のように `=` の直後に値を書かないよう、空の値を置き換えるときは空白を補う
+        let value = if node.value.is_empty() && !text[..node.value.start].ends_with(char::is_whitespace) {
+            format!(" {}", value)
+        } else {
+            value.clone()
+        };
+        document.replace_text(*index, node.value.start..end, &value);
+    }
+    if lines.is_empty() {
+        document.push_line(format!("{} = {}", key, value).trim_end());
+    }
+    !lines.is_empty()
+}
+
+// キーが定義されている行をすべて取り除く。定義されていた場合は true を返す
+fn unset_key(document: &mut Document, path: &[String]) -> bool {
+    let lines = find_key_lines(document, path);
+    for (index, _) in lines.iter().rev() {
+        document.remove_line(*index);
+    }
+    !lines.is_empty()
+}
+
+// そのまま書くと別の値として読み込まれる値（前後の空白、引用符や `\` で始まる・終わる値、
+// 改行やコメントのような文字列を含む値）は `"` で囲んでエスケープする
+fn format_raw_value(value: &str) -> String {
+    let needs_quote = value != value.trim()
+        || value.starts_with(['"', '\''])
+        || value.ends_with('\\')
+        || value.contains(['\n', '\r'])
+        || find_inline_comment(value).is_some();
+    if !needs_quote {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// 同じディレクトリの一時ファイルに書き込んでから名前を変更して置き換える。
+// シンボリックリンクはリンク先のファイルを置き換え、元のファイルのパーミッションを引き継ぐ
+fn write_atomically(path: &Path, contents: &str, backup: bool) -> io::Result<()> {
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = fs::File::create_new(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        if backup && path.exists() {
+            let mut backup_path = path.clone().into_os_string();
+            backup_path.push(".bak");
+            fs::copy(&path, backup_path)?;
+        }
+        fs::rename(&temp_path, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// `fmt [--check] FILES...`: ファイルを `key = value` の形式に整形して書き換える。
+// `--check` の場合は書き換えずに、整形されていないファイルを報告する
+fn run_fmt(args: &[OsString]) -> i32 {
+    let mut check = false;
+    let rest: Vec<OsString> = args.iter()
+        .filter(|&arg| {
+            if arg == "--check" {
+                check = true;
+            }
+            arg != "--check"
+        })
+        .cloned()
+        .collect();
+    let (options, paths) = parse_args(&rest);
+    let (files, mut failures) = get_text_files(&paths, &options.collect, options.lang);
+    let mut successes = 0;
+    let mut unformatted = 0;
+
+    for file_path in files {
+        let source = match fs::read_to_string(&file_path) {
+            Ok(source) => source,
+            Err(e) => {
+                report_path_error(&file_path, &PathError::Io(e), options.lang);
+                failures += 1;
+                continue;
+            }
+        };
+        let parsed = parse_config_partial(&source, &options.parse);
+        if !parsed.dropped.is_empty() {
+            match options.lang {
+                Lang::Ja => eprintln!("文法エラーのためファイルを整形できません ({})", file_path.display()),
+                Lang::En => eprintln!("cannot format file with syntax errors ({})", file_path.display()),
+            }
+            let diagnostics: Vec<Diagnostic> = parsed.dropped.iter()
+                .map(|e| e.to_diagnostic(Severity::Error, options.lang))
+                .collect();
+            report_diagnostics(&file_path, &source, &diagnostics, options.lang);
+            failures += 1;
+            continue;
+        }
+
+        let formatted = format_document(&Document::parse(&source), options.format.sort_keys);
+        successes += 1;
+        if formatted == source {
+            continue;
+        }
+        if check {
+            match options.lang {
+                Lang::Ja => eprintln!("整形されていません: {}", file_path.display()),
+                Lang::En => eprintln!("not formatted: {}", file_path.display()),
+            }
+            unformatted += 1;
+        } else if let Err(e) = write_atomically(&file_path, &formatted, false) {
+            match options.lang {
+                Lang::Ja => eprintln!("ファイルの書き込みエラー: {} ({})", e, file_path.display()),
+                Lang::En => eprintln!("failed to write file: {} ({})", e, file_path.display()),
+            }
+            failures += 1;
+        }
+    }
+
+    match exit_code(successes, failures) {
+        EXIT_SUCCESS if unformatted > 0 => EXIT_PARTIAL_FAILURE,
+        code => code,
+    }
+}
+
+// 定義行を `key = value`（キーはドット区切り、値は元の表記のまま）に、コメント行を前後の空白を除いた形に揃える。
+// 連続する空行は1行にまとめ、ファイルの先頭と末尾の空行は取り除く。
+// `sort` が true の場合は定義をキーの順に並べ替え、定義の直前のコメントはその定義とともに移動する。
+// 最初の定義の前の空行までのコメント（ファイルの見出し）と、最後の定義の後のコメントは移動しない
+fn format_document(document: &Document, sort: bool) -> String {
+    let mut groups: Vec<(Vec<String>, Vec<String>)> = Vec::new();
+    let mut pending = Vec::new();
+    for line in &document.lines {
+        let node = match &line.kind {
+            LineKind::Entry(node) => node,
+            LineKind::Blank => {
+                pending.push(String::new());
+                continue;
+            }
+            LineKind::Comment | LineKind::Invalid => {
+                pending.push(line.text.trim().to_string());
+                continue;
+            }
+        };
+        let raw_key = &line.text[node.key.clone()];
+        let path = parse_key_path(raw_key)
+            .map(|(path, _)| path.segments)
+            .unwrap_or_else(|_| vec![raw_key.to_string()]);
+        let mut text = format!("{}{} =", if node.ignore_failure { "-" } else { "" }, format_key(&path));
+        if !node.value.is_empty() {
+            text.push(' ');
+            text.push_str(&line.text[node.value.clone()]);
+        }
+        pending.push(text);
+        groups.push((path, std::mem::take(&mut pending)));
+    }
+
+    let mut header = Vec::new();
+    if sort {
+        if let Some((_, lines)) = groups.first_mut()
+            && let Some(blank) = lines.iter().rposition(String::is_empty)
+        {
+            header = lines.drain(..=blank).collect();
+        }
+        groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // 並べ替えた後は、コメントの付いた定義の前にだけ空行を置く
+        for (_, lines) in &mut groups {
+            lines.retain(|line| !line.is_empty());
+            if lines.len() > 1 {
+                lines.insert(0, String::new());
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut blank = false;
+    let lines = header.into_iter()
+        .chain(groups.into_iter().flat_map(|(_, lines)| lines))
+        .chain(pending);
+    for line in lines {
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
 fn main() {
-    let text_files = get_text_files(&env::args().skip(1).collect::<Vec<_>>());
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+    match args.first().and_then(|arg| arg.to_str()) {
+        Some(command @ ("set" | "unset")) => std::process::exit(run_edit(command, &args[1..])),
+        Some("get") => std::process::exit(run_get(&args[1..])),
+        Some("fmt") => std::process::exit(run_fmt(&args[1..])),
+        _ => {}
+    }
+
+    let (options, paths) = parse_args(&args);
+    if options.systemd {
+        std::process::exit(run_systemd(&options, &paths));
+    }
+
+    let (text_files, mut failures) = get_text_files(&paths, &options.collect, options.lang);
+    if options.merge {
+        std::process::exit(run_merged(text_files, failures, &options));
+    }
+
+    let mut successes = 0;
+    let mut reports = Vec::new();
 
     for file_path in text_files {
-        println!("=== ファイル: {} ===", file_path.display());
-        match parse_config_file(&file_path) {
-            Ok(config) => {
-                let json_output = format_as_json(&config);
-                println!("{}", serde_json::to_string_pretty(&json_output).unwrap());
+        let report = process_file(&file_path, &options);
+        if report.config.is_some() {
+            successes += 1;
+        } else {
+            failures += 1;
+        }
+        match options.output {
+            OutputMode::Text => {
+                if report.config.is_some() {
+                    println!("=== ファイル: {} ===", file_path.display());
+                    let json_output = report.config_output(&options.format);
+                    println!("{}", serde_json::to_string_pretty(&json_output).unwrap());
+                }
+            }
+            OutputMode::Ndjson => println!("{}", report.to_json(&options.format)),
+            OutputMode::Object | OutputMode::Records => reports.push(report),
+        }
+    }
+
+    let json_output = match options.output {
+        OutputMode::Object => {
+            let mut json_obj = serde_json::Map::new();
+            for report in &reports {
+                json_obj.insert(report.path.display().to_string(), report.config_output(&options.format));
             }
-            Err(e) => eprintln!("ファイルの読み込みエラー: {} ({})", e, file_path.display()),
+            serde_json::Value::Object(json_obj)
+        }
+        OutputMode::Records => {
+            serde_json::Value::Array(reports.iter().map(|r| r.to_json(&options.format)).collect())
         }
+        OutputMode::Text | OutputMode::Ndjson => serde_json::Value::Null,
+    };
+    if !json_output.is_null() {
+        println!("{}", serde_json::to_string_pretty(&json_output).unwrap());
+    }
+
+    std::process::exit(exit_code(successes, failures));
+}
+
+fn exit_code(successes: usize, failures: usize) -> i32 {
+    if failures == 0 {
+        EXIT_SUCCESS
+    } else if successes == 0 {
+        EXIT_FAILURE
+    } else {
+        EXIT_PARTIAL_FAILURE
     }
 }
 
 impl ConfigValue {
-    fn as_map_mut(&mut self) -> Option<&mut HashMap<String, ConfigValue>> {
+    fn as_map_mut(&mut self) -> Option<&mut IndexMap<String, ConfigValue>> {
         if let ConfigValue::Map(m) = self {
             Some(m)
         } else {
@@ -117,3 +1903,309 @@ impl ConfigValue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn fmt(source: &str, sort: bool) -> String {
+        format_document(&Document::parse(source), sort)
+    }
+
+    fn key_path(key: &str) -> (Vec<String>, Vec<usize>, Vec<usize>) {
+        let (path, empty) = parse_key_path(key).unwrap();
+        (path.segments, path.ends, empty)
+    }
+
+    fn strings(segments: &[&str]) -> Vec<String> {
+        segments.iter().map(|segment| segment.to_string()).collect()
+    }
+
+    fn scalar_and_map() -> (IndexMap<String, ConfigValue>, IndexMap<String, ConfigValue>) {
+        let scalar = parse_config_str("a = 1", &ParseOptions::default()).unwrap().config;
+        let map = parse_config_str("a.b = 2", &ParseOptions::default()).unwrap().config;
+        (scalar, map)
+    }
+
+    fn edited(source: &str, edit: impl FnOnce(&mut Document) -> bool) -> (String, bool) {
+        let mut document = Document::parse(source);
+        let found = edit(&mut document);
+        (document.to_string(), found)
+    }
+
+    fn set(source: &str, key: &str, value: &str) -> (String, bool) {
+        let options = EditOptions { backup: false, inline_comments: InlineCommentPolicy::None, lang: Lang::Ja };
+        edited(source, |document| set_key(document, key, &parse_cli_key(key), value, &options))
+    }
+
+    #[test]
+    fn syntax_error_in_continued_line_points_at_physical_line() {
+        let source = "a = \"x \\\n   y\\q\"\n";
+        let Err(ParseError::Syntax(errors)) = parse_config_str(source, &ParseOptions::default()) else {
+            panic!("expected a syntax error");
+        };
+        let diagnostic = errors[0].to_diagnostic(Severity::Error, Lang::En).to_json(source);
+        assert_eq!(diagnostic["line"], 2);
+        assert_eq!(diagnostic["column"], 5);
+        assert_eq!(diagnostic["end_column"], 7);
+    }
+
+    #[test]
+    fn infers_integers_in_every_radix() {
+        assert_eq!(infer_value("42"), ConfigValue::Int(42));
+        assert_eq!(infer_value("0x1F"), ConfigValue::Int(31));
+        assert_eq!(infer_value("0X1f"), ConfigValue::Int(31));
+        assert_eq!(infer_value("0o17"), ConfigValue::Int(15));
+        assert_eq!(infer_value("0755"), ConfigValue::Int(493));
+        assert_eq!(infer_value("0"), ConfigValue::Int(0));
+        assert_eq!(infer_value("08"), ConfigValue::String("08".to_string()));
+        assert_eq!(infer_value("-5"), ConfigValue::Int(-5));
+        assert_eq!(infer_value("+5"), ConfigValue::Int(5));
+        assert_eq!(infer_value("-0x10"), ConfigValue::Int(-16));
+        assert_eq!(infer_value("--5"), ConfigValue::String("--5".to_string()));
+        assert_eq!(infer_value("0x"), ConfigValue::String("0x".to_string()));
+    }
+
+    #[test]
+    fn infers_integers_up_to_u64_range() {
+        assert_eq!(infer_value("9223372036854775807"), ConfigValue::Int(i64::MAX));
+        assert_eq!(infer_value("-9223372036854775808"), ConfigValue::Int(i64::MIN));
+        assert_eq!(infer_value("18446744073692774399"), ConfigValue::UInt(18446744073692774399));
+        assert_eq!(infer_value("0xffffffffffffffff"), ConfigValue::UInt(u64::MAX));
+        assert_eq!(infer_value("18446744073709551616"), ConfigValue::String("18446744073709551616".to_string()));
+        assert_eq!(infer_value("-9223372036854775809"), ConfigValue::String("-9223372036854775809".to_string()));
+        assert_eq!(format_value(&ConfigValue::UInt(u64::MAX), &FormatOptions::default()), json!(u64::MAX));
+    }
+
+    #[test]
+    fn fmt_normalizes_entries_and_collapses_blank_lines() {
+        let source = "\n\n  # note  \nnet/ipv4/ip_forward=1\n\n\n\n-kernel.foo   =   bar # inline\nempty=\nlong = a \\\n   b\n\n";
+        assert_eq!(
+            fmt(source, false),
+            "# note\nnet.ipv4.ip_forward = 1\n\n-kernel.foo = bar # inline\nempty =\nlong = a b\n",
+        );
+    }
+
+    #[test]
+    fn fmt_sort_keeps_header_and_moves_comments_with_their_keys() {
+        let source = "# header\n\n# about z\nz = 1\n; about a\na = 2\n-m = 3\n# trailing\n";
+        assert_eq!(
+            fmt(source, true),
+            "# header\n\n; about a\na = 2\n-m = 3\n\n# about z\nz = 1\n# trailing\n",
+        );
+    }
+
+    #[test]
+    fn fmt_without_header_sorts_leading_comment_with_first_key() {
+        assert_eq!(fmt("# about b\nb = 1\na = 2\n", true), "a = 2\n\n# about b\nb = 1\n");
+    }
+
+    #[test]
+    fn fmt_is_idempotent() {
+        let source = "\n# header\n\n\n# about z\n z=1 \n\n; about a\n-a/b=\"x\"\nc = 1 \\\n 2\n\n\n# end\n\n";
+        for sort in [false, true] {
+            let once = fmt(source, sort);
+            assert_eq!(fmt(&once, sort), once);
+        }
+    }
+
+    #[test]
+    fn fmt_check_reports_unformatted_files_without_writing() {
+        let path = env::temp_dir().join(format!("fmt-check-{}.conf", std::process::id()));
+        let path_arg = path.to_str().unwrap();
+        fs::write(&path, "a=1\n").unwrap();
+
+        assert_eq!(run_fmt(&os_args(&["--check", path_arg])), EXIT_PARTIAL_FAILURE);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a=1\n");
+        assert_eq!(run_fmt(&os_args(&[path_arg])), EXIT_SUCCESS);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a = 1\n");
+        assert_eq!(run_fmt(&os_args(&["--check", path_arg])), EXIT_SUCCESS);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn default_ignores_skip_backups_and_package_leftovers() {
+        let options = CollectOptions::default();
+        for name in ["a.conf~", ".a.conf.swp", "a.conf.rpmsave", "a.conf.dpkg-old", "a.conf.ucf-dist", "#a.conf#", "README.md"] {
+            assert!(options.is_excluded(name), "{}", name);
+        }
+        for name in ["a.conf", "sysctl.conf", "readme.conf"] {
+            assert!(!options.is_excluded(name), "{}", name);
+        }
+        let options = CollectOptions { default_ignores: false, ..CollectOptions::default() };
+        assert!(!options.is_excluded("a.conf~"));
+    }
+
+    #[test]
+    fn key_path_reports_empty_segments() {
+        assert_eq!(key_path("a..b"), (strings(&["a", "b"]), vec![1, 4], vec![2]));
+        assert_eq!(key_path(".a"), (strings(&["a"]), vec![2], vec![0]));
+        assert_eq!(key_path("a."), (strings(&["a"]), vec![1], vec![1]));
+        assert_eq!(key_path("."), (Vec::new(), Vec::new(), vec![0]));
+    }
+
+    #[test]
+    fn key_path_keeps_quoted_segments_whole() {
+        assert_eq!(key_path("\"my.host\".port"), (strings(&["my.host", "port"]), vec![9, 14], Vec::new()));
+        assert_eq!(format_key(&strings(&["my.host", "port"])), "\"my.host\".port");
+    }
+
+    #[test]
+    fn key_path_rejects_misplaced_quotes() {
+        assert_eq!(parse_key_path("\"a\".b\""), Err((SyntaxErrorKind::InvalidKeyChar('"'), 5, 6)));
+        assert_eq!(parse_key_path("\"a\"b"), Err((SyntaxErrorKind::InvalidKeyChar('b'), 3, 4)));
+        assert_eq!(parse_key_path("\"a.b"), Err((SyntaxErrorKind::UnterminatedQuote, 0, 1)));
+    }
+
+    #[test]
+    fn key_path_with_slash_separator_turns_dots_into_slashes() {
+        let (segments, _, empty) = key_path("net/ipv4/conf/eth0.100/rp_filter");
+        assert_eq!(segments, strings(&["net", "ipv4", "conf", "eth0/100", "rp_filter"]));
+        assert!(empty.is_empty());
+        assert_eq!(format_key(&segments), "net.ipv4.conf.eth0/100.rp_filter");
+    }
+
+    #[test]
+    fn explicit_keys_take_precedence_over_glob() {
+        let root = env::temp_dir().join(format!("sysctl-root-{}", std::process::id()));
+        for interface in ["all", "eth0"] {
+            fs::create_dir_all(root.join("net/ipv4/conf").join(interface)).unwrap();
+            fs::write(root.join("net/ipv4/conf").join(interface).join("rp_filter"), "1\n").unwrap();
+        }
+        let options = ParseOptions { sysctl_root: Some(root.clone()), ..ParseOptions::default() };
+        let source = "net.ipv4.conf.eth0.rp_filter = 0\nnet.ipv4.conf.*.rp_filter = 2\n";
+        let parsed = parse_config_str(source, &options).unwrap();
+        assert_eq!(
+            format_as_json(&parsed.config, &FormatOptions::default()),
+            json!({ "net": { "ipv4": { "conf": { "eth0": { "rp_filter": 0 }, "all": { "rp_filter": 2 } } } } }),
+        );
+        let patterns: Vec<_> = parsed.entries.iter().map(|entry| entry.pattern.as_deref()).collect();
+        assert_eq!(patterns, [None, Some("net.ipv4.conf.*.rp_filter")]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cli_key_rejects_empty_and_malformed_keys() {
+        assert_eq!(cli_key_path(""), None);
+        assert_eq!(cli_key_path("a..b"), None);
+        assert_eq!(cli_key_path("a b"), None);
+        assert_eq!(cli_key_path("net/ipv4/ip_forward"), Some(vec!["net".into(), "ipv4".into(), "ip_forward".into()]));
+        assert!(parse_key_path("").is_ok_and(|(path, empty)| path.segments.is_empty() && empty.is_empty()));
+    }
+
+    #[test]
+    fn get_accepts_options_before_key() {
+        let (_, key, paths, default) = parse_get_args(&os_args(&["--default", "0", "net.ipv4.ip_forward"]));
+        assert_eq!(key, "net.ipv4.ip_forward");
+        assert!(paths.is_empty());
+        assert_eq!(default.as_deref(), Some("0"));
+
+        let (options, key, paths, default) = parse_get_args(&os_args(&["--split-values", "arr", "f.conf"]));
+        assert!(options.parse.split_values);
+        assert_eq!(key, "arr");
+        assert_eq!(paths, vec![PathBuf::from("f.conf")]);
+        assert_eq!(default, None);
+    }
+
+    #[test]
+    fn merge_applies_conflict_policy_across_files() {
+        let (scalar, map) = scalar_and_map();
+        let mut merged = scalar.clone();
+        assert_eq!(merge_config(&mut merged, map.clone(), ConflictPolicy::Error), Err(vec!["a".to_string()]));
+
+        let mut merged = scalar.clone();
+        merge_config(&mut merged, map.clone(), ConflictPolicy::LastWins).unwrap();
+        assert_eq!(merged, map);
+
+        let mut merged = scalar;
+        merge_config(&mut merged, map, ConflictPolicy::KeepScalar).unwrap();
+        let expected = parse_config_str("a._value = 1\na.b = 2", &ParseOptions::default()).unwrap().config;
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn provenance_reports_scalar_kept_under_value_key() {
+        let options = ParseOptions { conflict_policy: ConflictPolicy::KeepScalar, ..ParseOptions::default() };
+        let parsed = parse_config_str("log = x\nlog.file = y", &options).unwrap();
+        let provenance = format_provenance(
+            &parsed.config,
+            &[(Path::new("k.conf"), &parsed.entries)],
+            &FormatOptions::default(),
+        );
+        assert_eq!(provenance["log"]["origin"]["line"], 1);
+        assert_eq!(provenance["log"]["shadowed"], json!([]));
+    }
+
+    #[test]
+    fn split_key_matches_any_key_spelling() {
+        let (options, _) = parse_args(&os_args(&["--split-key", "net/ipv4/tcp_rmem"]));
+        for source in ["net/ipv4/tcp_rmem = 1 2 3", "net.ipv4.tcp_rmem = 1 2 3"] {
+            let config = parse_config_str(source, &options.parse).unwrap().config;
+            let path = parse_cli_key("net.ipv4.tcp_rmem");
+            assert_eq!(
+                lookup_config(&config, &path),
+                Some(&ConfigValue::Array(vec![ConfigValue::Int(1), ConfigValue::Int(2), ConfigValue::Int(3)])),
+            );
+        }
+    }
+
+    #[test]
+    fn set_replaces_value_in_place_on_crlf_file() {
+        let (out, found) = set("# keep\r\na  =  0\r\nb = 2\r\n", "a", "1");
+        assert!(found);
+        assert_eq!(out, "# keep\r\na  =  1\r\nb = 2\r\n");
+    }
+
+    #[test]
+    fn set_appends_to_file_without_final_newline() {
+        assert_eq!(set("a = 1", "b", "2"), ("a = 1\nb = 2\n".to_string(), false));
+        assert_eq!(set("a = 1\r\nc = 3", "b", "2").0, "a = 1\r\nc = 3\r\nb = 2\r\n");
+    }
+
+    #[test]
+    fn set_appends_after_line_ending_in_backslash() {
+        let (out, _) = set("a = 1 \\\n", "b", "2");
+        assert_eq!(out, "a = 1 \\\n\nb = 2\n");
+        let config = parse_config_str(&out, &ParseOptions::default()).unwrap().config;
+        assert_eq!(config.get("b"), Some(&ConfigValue::Int(2)));
+    }
+
+    #[test]
+    fn set_fills_empty_value() {
+        assert_eq!(set("a =\nb = 2\n", "a", "1").0, "a = 1\nb = 2\n");
+        assert_eq!(set("a = \n", "a", "1").0, "a = 1\n");
+    }
+
+    #[test]
+    fn set_finds_key_spelled_with_slashes() {
+        let (out, found) = set("# c\nnet/ipv4/ip_forward = 0\n", "net.ipv4.ip_forward", "1");
+        assert!(found);
+        assert_eq!(out, "# c\nnet/ipv4/ip_forward = 1\n");
+        let (out, found) = set("net.ipv4.ip_forward = 0\n", "net/ipv4/ip_forward", "1");
+        assert!(found);
+        assert_eq!(out, "net.ipv4.ip_forward = 1\n");
+    }
+
+    #[test]
+    fn unset_removes_continued_entry() {
+        let path = parse_cli_key("a");
+        let (out, found) = edited("# c\na = 1 \\\n  2\nb = 3\n", |document| unset_key(document, &path));
+        assert!(found);
+        assert_eq!(out, "# c\nb = 3\n");
+        let (out, found) = edited("b = 3\n", |document| unset_key(document, &path));
+        assert!(!found);
+        assert_eq!(out, "b = 3\n");
+    }
+
+    #[test]
+    fn empty_as_null_keeps_quoted_empty_string() {
+        let config = parse_config_str("a =\nb = \"\"\nc = ''", &ParseOptions::default()).unwrap().config;
+        let options = FormatOptions { empty_as_null: true, ..FormatOptions::default() };
+        assert_eq!(format_as_json(&config, &options), json!({ "a": null, "b": "", "c": "" }));
+        assert_eq!(format_as_json(&config, &FormatOptions::default()), json!({ "a": "", "b": "", "c": "" }));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::glob::{is_glob, Glob};
+
+// systemd-sysctl が設定ファイルを探すディレクトリ（優先度の高い順）
+const SYSCTL_D_DIRS: &[&str] = &[
+    "etc/sysctl.d",
+    "run/sysctl.d",
+    "usr/local/lib/sysctl.d",
+    "usr/lib/sysctl.d",
+];
+
+const SYSCTL_CONF: &str = "etc/sysctl.conf";
+
+// `root` 以下の sysctl.d ディレクトリから、適用する順に設定ファイルを返す。
+// 同名のファイルは優先度の高いディレクトリのものだけを使い、ファイル名の辞書順で並べる。
+// /dev/null へのシンボリックリンクなど通常のファイルでないものは、同名のファイルを隠すだけで読み込まない。
+// /etc/sysctl.conf は最後に適用する。ただし `99-sysctl.conf -> ../sysctl.conf` のように
+// sysctl.d のシンボリックリンクから読み込まれる場合は、その位置で1度だけ適用する
+pub fn config_files(root: &Path) -> (Vec<PathBuf>, Vec<(PathBuf, io::Error)>) {
+    let mut by_name: BTreeMap<OsString, PathBuf> = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for dir in SYSCTL_D_DIRS {
+        let dir = root.join(dir);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                errors.push((dir, e));
+                continue;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "conf") && !path.is_dir() {
+                by_name.entry(entry.file_name()).or_insert(path);
+            }
+        }
+    }
+
+    let mut files: Vec<PathBuf> = by_name.into_values().filter(|path| path.is_file()).collect();
+    let sysctl_conf = root.join(SYSCTL_CONF);
+    let canonical = fs::canonicalize(&sysctl_conf).ok();
+    let linked = files.iter().any(|path| fs::canonicalize(path).ok() == canonical);
+    if sysctl_conf.is_file() && !linked {
+        files.push(sysctl_conf);
+    }
+    (files, errors)
+}
+
+// `/proc/sys` などの sysctl ルート以下を探索し、グロブを含むキーに一致する実在のキーを辞書順で返す。
+// ディレクトリ名の `.` はキーの中では `/` として扱う（`eth0.100` は `eth0/100`）。
+// ルートの外に出ないよう、`.`・`..` になるセグメントには何も一致させない
+pub fn expand_glob_key(root: &Path, pattern: &[String]) -> Vec<Vec<String>> {
+    let mut keys = Vec::new();
+    walk_sysctl_tree(root, pattern, &mut Vec::new(), &mut keys);
+    keys
+}
+
+fn walk_sysctl_tree(dir: &Path, segments: &[String], prefix: &mut Vec<String>, keys: &mut Vec<Vec<String>>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        if dir.is_file() {
+            keys.push(prefix.clone());
+        }
+        return;
+    };
+
+    if !is_glob(segment) {
+        let name = segment.replace('/', ".");
+        if name == "." || name == ".." {
+            return;
+        }
+        prefix.push(segment.clone());
+        walk_sysctl_tree(&dir.join(name), rest, prefix, keys);
+        prefix.pop();
+        return;
+    }
+
+    let Ok(glob) = Glob::new(segment) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut names: Vec<String> = entries.filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    for name in names {
+        let segment = name.replace('.', "/");
+        if glob.is_match(&segment) {
+            prefix.push(segment);
+            walk_sysctl_tree(&dir.join(&name), rest, prefix, keys);
+            prefix.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    // テストごとに空の一時ディレクトリを作る
+    fn fixture(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("sysctld-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in SYSCTL_D_DIRS {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        root
+    }
+
+    fn segments(key: &str) -> Vec<String> {
+        key.split('.').map(str::to_string).collect()
+    }
+
+    // `/proc/sys` と同じ形の、キーごとにファイルがあるツリーを作る
+    fn sysctl_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("sysctl-tree-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["net/ipv4/conf/all", "net/ipv4/conf/eth0", "net/ipv4/conf/eth0.100", "net/ipv6"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["net/ipv4/conf/all/rp_filter", "net/ipv4/conf/eth0/rp_filter", "net/ipv4/conf/eth0.100/rp_filter"] {
+            fs::write(root.join(file), "1\n").unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn expands_glob_against_existing_keys() {
+        let root = sysctl_tree("expand");
+        let keys = expand_glob_key(&root, &segments("net.ipv4.conf.eth*.rp_filter"));
+        assert_eq!(keys, [
+            vec!["net", "ipv4", "conf", "eth0", "rp_filter"],
+            vec!["net", "ipv4", "conf", "eth0/100", "rp_filter"],
+        ]);
+        assert!(expand_glob_key(&root, &segments("net.ipv4.conf.*.missing")).is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dot_segments_do_not_leave_root() {
+        let root = sysctl_tree("dots");
+        let conf = root.join("net/ipv4/conf");
+        let pattern = vec!["..".to_string(), "conf".to_string(), "*".to_string(), "rp_filter".to_string()];
+        assert!(expand_glob_key(&conf.join("all"), &pattern).is_empty());
+        let pattern = vec!["//".to_string(), "*".to_string(), "rp_filter".to_string()];
+        assert!(expand_glob_key(&conf.join("all"), &pattern).is_empty());
+        let pattern = vec![".".to_string(), "*".to_string(), "rp_filter".to_string()];
+        assert!(expand_glob_key(&conf, &pattern).is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn relative(root: &Path, files: &[PathBuf]) -> Vec<String> {
+        files.iter()
+            .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn orders_by_file_name_and_masks_lower_priority_directories() {
+        let root = fixture("order");
+        fs::write(root.join("usr/lib/sysctl.d/10-a.conf"), "").unwrap();
+        fs::write(root.join("usr/lib/sysctl.d/20-b.conf"), "").unwrap();
+        fs::write(root.join("etc/sysctl.d/20-b.conf"), "").unwrap();
+        fs::write(root.join("run/sysctl.d/05-c.conf"), "").unwrap();
+        fs::write(root.join("etc/sysctl.d/ignored.txt"), "").unwrap();
+        fs::write(root.join(SYSCTL_CONF), "").unwrap();
+
+        let (files, errors) = config_files(&root);
+        assert!(errors.is_empty());
+        assert_eq!(
+            relative(&root, &files),
+            ["run/sysctl.d/05-c.conf", "usr/lib/sysctl.d/10-a.conf", "etc/sysctl.d/20-b.conf", "etc/sysctl.conf"],
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dev_null_symlink_masks_file() {
+        let root = fixture("mask");
+        fs::write(root.join("usr/lib/sysctl.d/50-default.conf"), "").unwrap();
+        symlink("/dev/null", root.join("etc/sysctl.d/50-default.conf")).unwrap();
+
+        let (files, _) = config_files(&root);
+        assert!(files.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sysctl_conf_linked_from_sysctl_d_is_applied_once_in_place() {
+        let root = fixture("linked");
+        fs::write(root.join(SYSCTL_CONF), "").unwrap();
+        symlink("../sysctl.conf", root.join("etc/sysctl.d/99-sysctl.conf")).unwrap();
+        fs::write(root.join("etc/sysctl.d/zz-late.conf"), "").unwrap();
+
+        let (files, _) = config_files(&root);
+        assert_eq!(relative(&root, &files), ["etc/sysctl.d/99-sysctl.conf", "etc/sysctl.d/zz-late.conf"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}